@@ -0,0 +1,231 @@
+use numpy::{PyArray1, PyArrayMethods, PyReadonlyArrayDyn, PyUntypedArrayMethods};
+use pyo3::exceptions::{PyRuntimeError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyByteArray, PyBytes, PyDict};
+use ravif::{Config, Img, PixelRange, RGBA8};
+use rgb::FromSlice;
+
+/// AVIF file with statistics about its contents. `bytes(image)` gives the file.
+#[pyclass(frozen, module = "ravif")]
+struct EncodedImage {
+    /// The AVIF file
+    #[pyo3(get)]
+    avif_file: Py<PyBytes>,
+    /// Size of the AV1 payload of the color channels
+    #[pyo3(get)]
+    color_byte_size: usize,
+    /// Size of the AV1 payload of the alpha channel (0 if alpha has been left out)
+    #[pyo3(get)]
+    alpha_byte_size: usize,
+}
+
+#[pymethods]
+impl EncodedImage {
+    fn __bytes__(&self, py: Python<'_>) -> Py<PyBytes> {
+        self.avif_file.clone_ref(py)
+    }
+
+    fn __len__(&self, py: Python<'_>) -> usize {
+        self.avif_file.bind(py).as_bytes().len()
+    }
+}
+
+impl EncodedImage {
+    fn new(py: Python<'_>, img: ravif::EncodedImage) -> Self {
+        Self {
+            avif_file: PyBytes::new(py, &img.avif_file).unbind(),
+            color_byte_size: img.color_byte_size,
+            alpha_byte_size: img.alpha_byte_size,
+        }
+    }
+}
+
+/// Encode RGBA pixels (non-premultiplied, alpha last) as AVIF.
+///
+/// `pixels` is a uint8 numpy array of shape (height, width, 4), or bytes together with `width` and `height`.
+/// Encoder options are keyword arguments.
+#[pyfunction]
+#[pyo3(signature = (pixels, width=None, height=None, **kwargs))]
+fn encode_rgba(py: Python<'_>, pixels: &Bound<'_, PyAny>, width: Option<usize>, height: Option<usize>, kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<EncodedImage> {
+    let config = config_from_kwargs(kwargs)?;
+    let (data, width, height) = pixel_data(pixels, width, height, 4)?;
+    let out = py.detach(|| ravif::encode_rgba(Img::new(data.as_rgba(), width, height), &config)).map_err(encoding_error)?;
+    Ok(EncodedImage::new(py, out))
+}
+
+/// Encode RGB pixels as AVIF.
+///
+/// `pixels` is a uint8 numpy array of shape (height, width, 3), or bytes together with `width` and `height`.
+/// Encoder options are keyword arguments.
+#[pyfunction]
+#[pyo3(signature = (pixels, width=None, height=None, **kwargs))]
+fn encode_rgb(py: Python<'_>, pixels: &Bound<'_, PyAny>, width: Option<usize>, height: Option<usize>, kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<EncodedImage> {
+    let config = config_from_kwargs(kwargs)?;
+    let (data, width, height) = pixel_data(pixels, width, height, 3)?;
+    let out = py.detach(|| ravif::encode_rgb(Img::new(data.as_rgb(), width, height), &config)).map_err(encoding_error)?;
+    Ok(EncodedImage::new(py, out))
+}
+
+/// Encode Y, U, V and optionally alpha planes as AVIF.
+///
+/// Each plane is a uint8 numpy array of shape (height, width), or bytes. With the `chroma_subsampling`
+/// option U and V planes are smaller: half of the width and/or height, rounded up.
+/// Encoder options are keyword arguments.
+#[pyfunction]
+#[pyo3(signature = (width, height, y, u, v, a=None, *, full_range=true, **kwargs))]
+#[allow(clippy::too_many_arguments)]
+fn encode_raw_planes(py: Python<'_>, width: usize, height: usize, y: &Bound<'_, PyAny>, u: &Bound<'_, PyAny>, v: &Bound<'_, PyAny>, a: Option<&Bound<'_, PyAny>>, full_range: bool, kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<EncodedImage> {
+    let config = config_from_kwargs(kwargs)?;
+    let (chroma_width, chroma_height) = config.chroma_subsampling.chroma_size(width, height);
+    let plane = |p, width, height| pixel_data(p, Some(width), Some(height), 1).map(|(data, ..)| data);
+    let (y, u, v) = (plane(y, width, height)?, plane(u, chroma_width, chroma_height)?, plane(v, chroma_width, chroma_height)?);
+    let a = a.map(|a| plane(a, width, height)).transpose()?;
+    let range = if full_range { PixelRange::Full } else { PixelRange::Limited };
+    let out = py.detach(|| ravif::encode_raw_planes(width, height, &y, &u, &v, a.as_deref(), range, &config)).map_err(encoding_error)?;
+    Ok(EncodedImage::new(py, out))
+}
+
+/// Make RGB of fully-transparent pixels compress better, without changing how the image looks.
+///
+/// Takes RGBA pixels like `encode_rgba()`, and returns a uint8 numpy array of shape (height, width, 4).
+#[pyfunction]
+#[pyo3(signature = (pixels, width=None, height=None))]
+fn cleared_alpha<'py>(py: Python<'py>, pixels: &Bound<'py, PyAny>, width: Option<usize>, height: Option<usize>) -> PyResult<Bound<'py, PyAny>> {
+    let (data, width, height) = pixel_data(pixels, width, height, 4)?;
+    let cleared = py.detach(|| {
+        let img = ravif::cleared_alpha(Img::new(data.as_rgba().to_vec(), width, height));
+        img.into_buf().into_iter().flat_map(|px: RGBA8| [px.r, px.g, px.b, px.a]).collect::<Vec<u8>>()
+    });
+    Ok(PyArray1::from_vec(py, cleared).reshape([height, width, 4])?.into_any())
+}
+
+/// Copies pixels out of a numpy array or bytes-like object, and checks their dimensions.
+///
+/// The copy is necessary, because other Python threads could modify the array while the GIL is released.
+fn pixel_data(pixels: &Bound<'_, PyAny>, width: Option<usize>, height: Option<usize>, channels: usize) -> PyResult<(Vec<u8>, usize, usize)> {
+    let bytes = if let Ok(bytes) = pixels.cast::<PyBytes>() {
+        Some(bytes.as_bytes().to_vec())
+    } else if let Ok(bytes) = pixels.cast::<PyByteArray>() {
+        Some(bytes.to_vec())
+    } else {
+        None
+    };
+    if let Some(data) = bytes {
+        let (width, height) = width.zip(height).ok_or_else(|| PyValueError::new_err("width and height are required when the pixels are bytes"))?;
+        if width.checked_mul(height).and_then(|px| px.checked_mul(channels)) != Some(data.len()) {
+            return Err(PyValueError::new_err(format!("expected width * height * {} = {}x{}x{} bytes, got {}", channels, width, height, channels, data.len())));
+        }
+        return Ok((data, width, height));
+    }
+
+    let array = pixels.extract::<PyReadonlyArrayDyn<'_, u8>>()
+        .map_err(|_| PyTypeError::new_err("expected a uint8 numpy array, bytes or bytearray"))?;
+    let (h, w) = match *array.shape() {
+        [h, w, c] if c == channels => (h, w),
+        [h, w] if channels == 1 => (h, w),
+        ref shape => return Err(PyValueError::new_err(format!("expected an array of shape (height, width, {}), got {:?}", channels, shape))),
+    };
+    if width.is_some_and(|width| width != w) || height.is_some_and(|height| height != h) {
+        return Err(PyValueError::new_err("width and height don't match the shape of the array"));
+    }
+    let data = match array.as_slice() {
+        Ok(slice) => slice.to_vec(),
+        // Strided arrays are iterated in logical order
+        Err(_) => array.as_array().iter().copied().collect(),
+    };
+    Ok((data, w, h))
+}
+
+fn config_from_kwargs(kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<Config> {
+    let mut config = Config::default();
+    let kwargs = match kwargs {
+        Some(kwargs) => kwargs,
+        None => return Ok(config),
+    };
+    for (key, value) in kwargs.iter() {
+        let key: String = key.extract()?;
+        match key.as_str() {
+            "quality" => config.quality = value.extract()?,
+            "alpha_quality" => config.alpha_quality = value.extract()?,
+            "speed" => config.speed = value.extract()?,
+            "premultiplied_alpha" => config.premultiplied_alpha = value.extract()?,
+            "color_space" => config.color_space = value.extract::<String>()?.parse().map_err(encoding_error)?,
+            "matrix_coefficients" => config.matrix_coefficients = value.extract::<String>()?.parse().map_err(encoding_error)?,
+            "color_primaries" => config.color_primaries = value.extract::<String>()?.parse().map_err(encoding_error)?,
+            "transfer_characteristics" => config.transfer_characteristics = value.extract::<String>()?.parse().map_err(encoding_error)?,
+            "bit_depth" => config.bit_depth = value.extract()?,
+            "chroma_subsampling" => config.chroma_subsampling = value.extract::<String>()?.parse().map_err(encoding_error)?,
+            "sharp_yuv" => config.sharp_yuv = value.extract()?,
+            "threads" => config.threads = value.extract()?,
+            "max_width" => config.limits.max_width = value.extract()?,
+            "max_height" => config.limits.max_height = value.extract()?,
+            "max_pixels" => config.limits.max_pixels = value.extract()?,
+            "max_memory" => config.limits.max_memory = value.extract()?,
+            _ => return Err(PyTypeError::new_err(format!("unexpected keyword argument '{}'", key))),
+        }
+    }
+    config.validate().map_err(encoding_error)?;
+    Ok(config)
+}
+
+fn encoding_error(err: ravif::Error) -> PyErr {
+    match err {
+        ravif::Error::TooFewPixels | ravif::Error::LimitExceeded(_) | ravif::Error::UnsupportedConfig(_) => PyValueError::new_err(err.to_string()),
+        err => PyRuntimeError::new_err(err.to_string()),
+    }
+}
+
+/// AVIF image encoder based on rav1e
+#[pymodule]
+#[pyo3(name = "ravif")]
+fn ravif_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<EncodedImage>()?;
+    m.add_function(wrap_pyfunction!(encode_rgba, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_rgb, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_raw_planes, m)?)?;
+    m.add_function(wrap_pyfunction!(cleared_alpha, m)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+fn with_python(f: impl FnOnce(Python<'_>)) {
+    Python::initialize();
+    Python::attach(f)
+}
+
+#[test]
+fn kwargs() {
+    with_python(|py| {
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("quality", 70).unwrap();
+        kwargs.set_item("chroma_subsampling", "420").unwrap();
+        kwargs.set_item("matrix_coefficients", "bt601").unwrap();
+        let config = config_from_kwargs(Some(&kwargs)).unwrap();
+        assert_eq!(70., config.quality);
+        assert_eq!(ravif::ChromaSubsampling::Yuv420, config.chroma_subsampling);
+        assert_eq!(ravif::MatrixCoefficients::Bt601, config.matrix_coefficients);
+
+        fn bad<'py>(py: Python<'py>, key: &str, value: impl IntoPyObject<'py>) -> PyErr {
+            let kwargs = PyDict::new(py);
+            kwargs.set_item(key, value).unwrap();
+            config_from_kwargs(Some(&kwargs)).unwrap_err()
+        }
+        assert!(bad(py, "quality", 0).is_instance_of::<PyValueError>(py));
+        assert!(bad(py, "speed", 11).is_instance_of::<PyValueError>(py));
+        assert!(bad(py, "bit_depth", 9).is_instance_of::<PyValueError>(py));
+        assert!(bad(py, "color_space", "xyz").is_instance_of::<PyValueError>(py));
+        assert!(bad(py, "qualty", 70).is_instance_of::<PyTypeError>(py));
+    });
+}
+
+#[test]
+fn bytes_pixel_data() {
+    with_python(|py| {
+        let bytes = PyBytes::new(py, &[1, 2, 3, 4, 5, 6]);
+        let (data, width, height) = pixel_data(&bytes, Some(2), Some(1), 3).unwrap();
+        assert_eq!((vec![1, 2, 3, 4, 5, 6], 2, 1), (data, width, height));
+
+        assert!(pixel_data(&bytes, Some(2), Some(2), 3).unwrap_err().is_instance_of::<PyValueError>(py));
+        assert!(pixel_data(&bytes, None, None, 3).unwrap_err().is_instance_of::<PyValueError>(py));
+    });
+}
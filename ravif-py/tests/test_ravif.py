@@ -0,0 +1,52 @@
+import numpy as np
+import pytest
+
+import ravif
+
+
+def is_avif(data):
+    return data[4:12] == b"ftypavif"
+
+
+def test_encode_rgba_array():
+    pixels = np.zeros((6, 9, 4), dtype=np.uint8)
+    pixels[..., 0] = np.arange(9) * 28
+    pixels[..., 3] = 255
+    avif = ravif.encode_rgba(pixels, quality=70, speed=10)
+    assert is_avif(bytes(avif))
+    assert len(avif) == len(bytes(avif))
+    assert avif.alpha_byte_size == 0
+
+
+def test_encode_rgb_bytes():
+    avif = ravif.encode_rgb(bytes(range(4 * 3 * 3)), 4, 3, speed=10, chroma_subsampling="420", sharp_yuv=True)
+    assert is_avif(bytes(avif))
+
+
+def test_encode_raw_planes():
+    y = np.full((5, 5), 100, dtype=np.uint8)
+    uv = np.full((3, 3), 128, dtype=np.uint8)
+    avif = ravif.encode_raw_planes(5, 5, y, uv, uv, speed=10, chroma_subsampling="420")
+    assert is_avif(bytes(avif))
+
+
+def test_cleared_alpha():
+    pixels = np.full((2, 2, 4), 200, dtype=np.uint8)
+    pixels[0, 0, 3] = 0
+    cleared = ravif.cleared_alpha(pixels)
+    assert cleared.shape == (2, 2, 4)
+    assert cleared[0, 0, 3] == 0
+
+
+def test_invalid_arguments():
+    pixels = np.zeros((2, 2, 4), dtype=np.uint8)
+    with pytest.raises(ValueError):
+        ravif.encode_rgba(pixels, quality=0)
+    with pytest.raises(ValueError):
+        ravif.encode_rgba(pixels, bit_depth=9)
+    with pytest.raises(ValueError):
+        ravif.encode_rgb(np.zeros((2, 2, 4), dtype=np.uint8))
+    with pytest.raises(TypeError):
+        ravif.encode_rgba(pixels, qualty=70)
+    with pytest.raises(TypeError):
+        ravif.encode_rgba("pixels")
@@ -1,7 +1,24 @@
+use crate::chroma::downsample;
+use crate::chroma::ChromaSubsampling;
+use crate::cicp::ColorPrimaries;
+use crate::cicp::MatrixCoefficients;
+use crate::cicp::TransferCharacteristics;
+use crate::error::Error;
+use crate::limits::Limits;
+use crate::progress::CancelFlag;
+use crate::progress::ProgressCallback;
+use crate::sharpyuv::sharp_yuv;
 use imgref::Img;
 use rav1e::prelude::*;
+use rgb::RGB16;
 use rgb::RGB8;
+use rgb::RGBA16;
 use rgb::RGBA8;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
 
 /// See [`Config`]
 #[repr(C)]
@@ -11,11 +28,19 @@ pub enum ColorSpace {
     RGB,
 }
 
+named_values!(ColorSpace, "Color space" {
+    YCbCr => "ycbcr",
+    RGB => "rgb",
+});
+
 /// Encoder configuration struct
 ///
 /// See [`encode_rgba`](crate::encode_rgba)
-#[repr(C)]
-#[derive(Debug, Copy, Clone)]
+///
+/// With the `serde` feature it can be deserialized from any subset of its fields.
+/// The progress callback and cancel flag are not serialized.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default, deny_unknown_fields))]
 pub struct EncConfig {
     /// 0-100 scale
     pub quality: f32,
@@ -27,8 +52,103 @@ pub struct EncConfig {
     pub premultiplied_alpha: bool,
     /// Which pixel format to use in AVIF file. RGB tends to give larger files.
     pub color_space: ColorSpace,
+    /// How RGB is converted to YCbCr. Ignored with `ColorSpace::RGB`.
+    pub matrix_coefficients: MatrixCoefficients,
+    /// Primaries of the pixels' color space. Pixels are not converted, this only labels them.
+    pub color_primaries: ColorPrimaries,
+    /// Transfer function of the pixels. Pixels are not converted, this only labels them.
+    pub transfer_characteristics: TransferCharacteristics,
+    /// Bits per channel in the AVIF file: 8, 10 or 12. Higher depths prevent banding in smooth gradients.
+    pub bit_depth: u8,
+    /// Resolution of color relative to brightness. Subsampling makes smaller files, but blurs colors.
+    /// Can't be used with `ColorSpace::RGB`.
+    pub chroma_subsampling: ChromaSubsampling,
+    /// Slower RGB to YCbCr conversion that adjusts brightness to keep edges between colors sharp
+    /// when chroma is subsampled. It has no effect without chroma subsampling.
+    pub sharp_yuv: bool,
     /// How many threads should be used (0 = match core count)
     pub threads: usize,
+    /// Receives progress of the encoding
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub progress: Option<ProgressCallback>,
+    /// Stops encoding early when cancelled
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub cancel: Option<CancelFlag>,
+    /// Images larger than this are rejected before encoding
+    pub limits: Limits,
+    /// Overrides rav1e settings chosen by `speed` and quality, for both color and alpha
+    pub speed_tweaks: SpeedTweakOverrides,
+}
+
+/// AVIF file returned by the encoding functions, with statistics about its contents
+#[derive(Debug, Clone)]
+pub struct EncodedImage {
+    /// The AVIF file
+    pub avif_file: Vec<u8>,
+    /// Size of the AV1 payload of the color channels
+    pub color_byte_size: usize,
+    /// Size of the AV1 payload of the alpha channel (0 if alpha has been left out)
+    pub alpha_byte_size: usize,
+    /// Time spent in rav1e encoding the color channels
+    pub color_encode_time: Duration,
+    /// Time spent in rav1e encoding the alpha channel
+    pub alpha_encode_time: Duration,
+}
+
+impl Default for EncConfig {
+    /// Same defaults as the `cavif` command-line tool
+    fn default() -> Self {
+        Self {
+            quality: 80.,
+            alpha_quality: 90.,
+            speed: 4,
+            premultiplied_alpha: false,
+            color_space: ColorSpace::YCbCr,
+            matrix_coefficients: MatrixCoefficients::Bt709,
+            color_primaries: ColorPrimaries::Bt709,
+            transfer_characteristics: TransferCharacteristics::Srgb,
+            bit_depth: 8,
+            chroma_subsampling: ChromaSubsampling::Yuv444,
+            sharp_yuv: false,
+            threads: 0,
+            progress: None,
+            cancel: None,
+            limits: Limits::default(),
+            speed_tweaks: SpeedTweakOverrides::default(),
+        }
+    }
+}
+
+impl EncConfig {
+    /// Check that settings are in their valid ranges, and can be encoded together.
+    ///
+    /// The encoding functions call it too, so this is only needed to report errors before encoding.
+    pub fn validate(&self) -> Result<(), Error> {
+        if !(1. ..=100.).contains(&self.quality) || !(1. ..=100.).contains(&self.alpha_quality) {
+            return Err(Error::UnsupportedConfig("Quality must be between 1 and 100".into()));
+        }
+        if self.speed > 10 {
+            return Err(Error::UnsupportedConfig("Speed must be between 0 and 10".into()));
+        }
+        self.speed_tweaks_for(self.quality).validate()?;
+        if !matches!(self.bit_depth, 8 | 10 | 12) {
+            return Err(Error::UnsupportedConfig(format!("Bit depth must be 8, 10 or 12, not {}", self.bit_depth)));
+        }
+        if matches!(self.color_space, ColorSpace::RGB) && self.chroma_subsampling != ChromaSubsampling::Yuv444 {
+            return Err(Error::UnsupportedConfig("Chroma subsampling can't be used with the RGB color space".into()));
+        }
+        Ok(())
+    }
+
+    /// Tweaks for `speed` and `quality`, with [`speed_tweaks`](Self::speed_tweaks) applied
+    pub(crate) fn speed_tweaks_for(&self, quality: f32) -> SpeedTweaks {
+        self.speed_tweaks.apply(SpeedTweaks::from_my_preset(self.speed, quality as _))
+    }
+
+    /// Whether [`sharp_yuv`](Self::sharp_yuv) is enabled and has an effect, i.e. RGB is converted to subsampled YCbCr
+    pub fn uses_sharp_yuv(&self) -> bool {
+        self.sharp_yuv && self.chroma_subsampling != ChromaSubsampling::Yuv444 && matches!(self.color_space, ColorSpace::YCbCr)
+    }
 }
 
 /// Make a new AVIF image from RGBA pixels (non-premultiplied, alpha last)
@@ -50,40 +170,73 @@ pub struct EncConfig {
 ///
 /// It's highly recommended to apply [`cleared_alpha`](crate::cleared_alpha) first.
 ///
-/// returns AVIF file, size of color metadata, size of alpha metadata overhead
-pub fn encode_rgba(buffer: Img<&[RGBA8]>, config: &EncConfig) -> Result<(Vec<u8>, usize, usize), Box<dyn std::error::Error + Send + Sync>> {
+/// returns AVIF file with size of color and alpha data
+pub fn encode_rgba(buffer: Img<&[RGBA8]>, config: &EncConfig) -> Result<EncodedImage, Error> {
     let width = buffer.width();
     let height = buffer.height();
     if buffer.buf().len() < width * height {
-        return Err("Too few pixels".into());
+        return Err(Error::TooFewPixels);
     }
-    let mut y_plane = Vec::with_capacity(width*height);
-    let mut u_plane = Vec::with_capacity(width*height);
-    let mut v_plane = Vec::with_capacity(width*height);
-    let mut a_plane = Vec::with_capacity(width*height);
-    for px in buffer.pixels() {
-        let (y,u,v) = match config.color_space {
-            ColorSpace::YCbCr => {
-                let y  = 0.2126 * px.r as f32 + 0.7152 * px.g as f32 + 0.0722 * px.b as f32;
-                let cb = (px.b as f32 - y) * (0.5/(1.-0.0722));
-                let cr = (px.r as f32 - y) * (0.5/(1.-0.2126));
-
-                (y.round() as u8, (cb + 128.).round() as u8, (cr + 128.).round() as u8)
-            },
-            ColorSpace::RGB => {
-                (px.g, px.b, px.r)
-            },
-        };
-        y_plane.push(y);
-        u_plane.push(u);
-        v_plane.push(v);
-        a_plane.push(px.a);
+    encode_rgba_pixels(width, height, buffer.pixels(), config)
+}
+
+/// Make a new AVIF image from RGBA pixels (non-premultiplied, alpha last) read from an iterator
+///
+/// The iterator must yield `width * height` pixels, row by row. This allows converting pixels
+/// from other layouts (e.g. BGRA or bottom-up rows) while the encoder reads them, without
+/// making a converted copy of the whole image first.
+///
+/// returns AVIF file with size of color and alpha data
+pub fn encode_rgba_pixels(width: usize, height: usize, pixels: impl IntoIterator<Item = RGBA8>, config: &EncConfig) -> Result<EncodedImage, Error> {
+    config.limits.check(width, height, config.bit_depth, config.uses_sharp_yuv(), 0)?;
+    let pixels = pixels.into_iter().map(|px| RGBA16::new(px.r.into(), px.g.into(), px.b.into(), px.a.into()));
+    config.validate()?;
+    if config.bit_depth > 8 {
+        encode_rgba_pixels_as::<u16>(width, height, pixels, 8, config)
+    } else {
+        encode_rgba_pixels_as::<u8>(width, height, pixels, 8, config)
+    }
+}
+
+/// Make a new AVIF image from 16-bit RGBA pixels (non-premultiplied, alpha last)
+///
+/// Use it with `config.bit_depth` of 10 or 12 to keep more precision than 8-bit pixels have.
+/// With 8-bit depth the pixels are rounded to 8 bits.
+///
+/// If all pixels are opaque, alpha channel will be left out automatically.
+///
+/// returns AVIF file with size of color and alpha data
+pub fn encode_rgba16(buffer: Img<&[RGBA16]>, config: &EncConfig) -> Result<EncodedImage, Error> {
+    let width = buffer.width();
+    let height = buffer.height();
+    if buffer.buf().len() < width * height {
+        return Err(Error::TooFewPixels);
     }
+    config.limits.check(width, height, config.bit_depth, config.uses_sharp_yuv(), 0)?;
+    config.validate()?;
+    if config.bit_depth > 8 {
+        encode_rgba_pixels_as::<u16>(width, height, buffer.pixels(), 16, config)
+    } else {
+        encode_rgba_pixels_as::<u8>(width, height, buffer.pixels(), 16, config)
+    }
+}
+
+/// Pixels have `input_depth` bits. `P` must be large enough for `config.bit_depth`.
+fn encode_rgba_pixels_as<P: Pixel>(width: usize, height: usize, pixels: impl IntoIterator<Item = RGBA16>, input_depth: u8, config: &EncConfig) -> Result<EncodedImage, Error> {
+    let depth = config.bit_depth;
+    let opaque = max_value(input_depth);
+    let mut a_plane = Vec::with_capacity(width*height);
+    let mut use_alpha = false;
+    let pixels = pixels.into_iter().map(|px| {
+        a_plane.push(P::cast_from(rescale(px.a, input_depth, depth)));
+        use_alpha |= px.a != opaque;
+        RGB16::new(px.r, px.g, px.b)
+    });
+    let (y_plane, u_plane, v_plane) = yuv_planes(width, height, pixels, input_depth, config)?;
 
-    let use_alpha = a_plane.iter().copied().any(|b| b != 255);
     let color_pixel_range = PixelRange::Full;
 
-    encode_raw_planes(width, height, &y_plane, &u_plane, &v_plane, if use_alpha { Some(&a_plane) } else { None }, color_pixel_range, config)
+    encode_planes(width, height, &y_plane, &u_plane, &v_plane, if use_alpha { Some(&a_plane) } else { None }, color_pixel_range, depth, config)
 }
 
 /// Make a new AVIF image from RGB pixels
@@ -101,104 +254,300 @@ pub fn encode_rgba(buffer: Img<&[RGBA8]>, config: &EncConfig) -> Result<(Vec<u8>
 /// let pixels_rgba = pixels_u8.as_rgb();
 /// ```
 ///
-/// returns AVIF file, size of color metadata
-pub fn encode_rgb(buffer: Img<&[RGB8]>, config: &EncConfig) -> Result<(Vec<u8>, usize), Box<dyn std::error::Error + Send + Sync>> {
+/// returns AVIF file with size of color data
+pub fn encode_rgb(buffer: Img<&[RGB8]>, config: &EncConfig) -> Result<EncodedImage, Error> {
     let width = buffer.width();
     let height = buffer.height();
     if buffer.buf().len() < width * height {
-        return Err("Too few pixels".into());
+        return Err(Error::TooFewPixels);
+    }
+    config.limits.check(width, height, config.bit_depth, config.uses_sharp_yuv(), 0)?;
+    config.validate()?;
+    if config.bit_depth > 8 {
+        encode_rgb_as::<u16>(buffer, config)
+    } else {
+        encode_rgb_as::<u8>(buffer, config)
+    }
+}
+
+/// `P` must be large enough for `config.bit_depth`
+fn encode_rgb_as<P: Pixel>(buffer: Img<&[RGB8]>, config: &EncConfig) -> Result<EncodedImage, Error> {
+    let width = buffer.width();
+    let height = buffer.height();
+    let pixels = buffer.pixels().map(|px| RGB16::new(px.r.into(), px.g.into(), px.b.into()));
+    let (y_plane, u_plane, v_plane) = yuv_planes::<P>(width, height, pixels, 8, config)?;
+
+    let color_pixel_range = PixelRange::Full;
+
+    encode_planes(width, height, &y_plane, &u_plane, &v_plane, None, color_pixel_range, config.bit_depth, config)
+}
+
+/// Y, U and V
+type Planes<P> = (Vec<P>, Vec<P>, Vec<P>);
+
+/// Converts `width * height` pixels with `input_depth` bits to Y, U and V planes (G, B and R for `ColorSpace::RGB`)
+/// with `config.bit_depth` bits, and subsampled chroma
+fn yuv_planes<P: Pixel>(width: usize, height: usize, pixels: impl Iterator<Item = RGB16>, input_depth: u8, config: &EncConfig) -> Result<Planes<P>, Error> {
+    let depth = config.bit_depth;
+    let subsampling = config.chroma_subsampling;
+    let pixels = pixels.take(width*height);
+    if config.uses_sharp_yuv() {
+        let rgb: Vec<_> = pixels.map(|px| normalized(px, input_depth)).collect();
+        if rgb.len() < width * height {
+            return Err(Error::TooFewPixels);
+        }
+        let (y, cb, cr) = sharp_yuv(&rgb, width, height, subsampling, config.matrix_coefficients);
+        let quantize = |v: f32, chroma| P::cast_from(quantize(v, chroma, depth));
+        return Ok((
+            y.into_iter().map(|y| quantize(y, false)).collect(),
+            cb.into_iter().map(|cb| quantize(cb, true)).collect(),
+            cr.into_iter().map(|cr| quantize(cr, true)).collect(),
+        ));
     }
+
     let mut y_plane = Vec::with_capacity(width*height);
     let mut u_plane = Vec::with_capacity(width*height);
     let mut v_plane = Vec::with_capacity(width*height);
-    for px in buffer.pixels() {
-        let (y,u,v) = match config.color_space {
-            ColorSpace::YCbCr => {
-                let y  = 0.2126 * px.r as f32 + 0.7152 * px.g as f32 + 0.0722 * px.b as f32;
-                let cb = (px.b as f32 - y) * (0.5/(1.-0.0722));
-                let cr = (px.r as f32 - y) * (0.5/(1.-0.2126));
-
-                (y.round() as u8, (cb + 128.).round() as u8, (cr + 128.).round() as u8)
-            },
-            ColorSpace::RGB => {
-                (px.g, px.b, px.r)
-            },
-        };
-        y_plane.push(y);
-        u_plane.push(u);
-        v_plane.push(v);
+    for px in pixels {
+        let (y,u,v) = rgb_to_yuv(px, input_depth, config.color_space, config.matrix_coefficients, depth);
+        y_plane.push(P::cast_from(y));
+        u_plane.push(P::cast_from(u));
+        v_plane.push(P::cast_from(v));
+    }
+    if y_plane.len() < width * height {
+        return Err(Error::TooFewPixels);
+    }
+    if subsampling == ChromaSubsampling::Yuv444 {
+        return Ok((y_plane, u_plane, v_plane));
     }
+    Ok((y_plane, downsample(&u_plane, width, height, subsampling), downsample(&v_plane, width, height, subsampling)))
+}
 
-    let color_pixel_range = PixelRange::Full;
+/// Y, U and V samples with `depth` bits (G, B and R for `ColorSpace::RGB`) from RGB with `input_depth` bits
+fn rgb_to_yuv(px: RGB16, input_depth: u8, color_space: ColorSpace, matrix: MatrixCoefficients, depth: u8) -> (u16, u16, u16) {
+    match color_space {
+        ColorSpace::YCbCr => {
+            let [y, cb, cr] = matrix.rgb_to_ycbcr(normalized(px, input_depth));
+            (quantize(y, false, depth), quantize(cb, true, depth), quantize(cr, true, depth))
+        },
+        ColorSpace::RGB => {
+            (rescale(px.g, input_depth, depth), rescale(px.b, input_depth, depth), rescale(px.r, input_depth, depth))
+        },
+    }
+}
+
+/// Full-range sample with `depth` bits from normalized Y, or from Cb or Cr centered at 0
+fn quantize(value: f32, chroma: bool, depth: u8) -> u16 {
+    let max = max_value(depth) as f32;
+    let offset = if chroma { (1 << (depth - 1)) as f32 } else { 0. };
+    (value * max + offset).round().clamp(0., max) as u16
+}
+
+/// RGB in 0..=1 range
+fn normalized(px: RGB16, input_depth: u8) -> [f32; 3] {
+    let max = max_value(input_depth) as f32;
+    [px.r as f32 / max, px.g as f32 / max, px.b as f32 / max]
+}
+
+fn max_value(depth: u8) -> u16 {
+    u16::MAX >> (16 - depth)
+}
+
+/// Convert a full-range sample from `from_depth` bits to `to_depth` bits, so that
+/// the maximum value of one depth becomes the maximum value of the other.
+fn rescale(sample: u16, from_depth: u8, to_depth: u8) -> u16 {
+    if to_depth >= from_depth {
+        // replicating the high bits in the low ones is the same as scaling by the ratio of the maximums
+        let shift = to_depth - from_depth;
+        sample << shift | sample >> (from_depth - shift)
+    } else {
+        let (from_max, to_max) = (u32::from(max_value(from_depth)), u32::from(max_value(to_depth)));
+        ((u32::from(sample) * to_max + from_max / 2) / from_max) as u16
+    }
+}
 
-    let (avif, heif_bloat, _) = encode_raw_planes(width, height, &y_plane, &u_plane, &v_plane, None, color_pixel_range, config)?;
-    Ok((avif, heif_bloat))
+/// Convert an 8-bit sample to `depth` bits
+fn upscale(sample: u8, depth: u8, full_range: bool) -> u16 {
+    if full_range {
+        rescale(sample.into(), 8, depth)
+    } else {
+        // limited range is defined as 16-235 shifted left
+        u16::from(sample) << (depth - 8)
+    }
 }
 
-/// If config.color_space is ColorSpace::YCbCr, then it takes 8-bit BT709 color space.
+/// If config.color_space is ColorSpace::YCbCr, then the planes must have been made with `config.matrix_coefficients`
+/// (BT.709 by default). The file is labelled with it, and with `config.color_primaries` and `config.transfer_characteristics`.
 ///
-/// Alpha always uses full range. Chroma subsampling is not supported, and it's a bad idea for AVIF anyway.
+/// If `config.bit_depth` is higher than 8, the samples are scaled up to it.
 ///
-/// returns AVIF file, size of color metadata, size of alpha metadata overhead
-pub fn encode_raw_planes(width: usize, height: usize, y_plane: &[u8], u_plane: &[u8], v_plane: &[u8], a_plane: Option<&[u8]>, color_pixel_range: PixelRange, config: &EncConfig) -> Result<(Vec<u8>, usize, usize), Box<dyn std::error::Error + Send + Sync>> {
-    if y_plane.len() < width * height {
-        return Err("Too few pixels".into());
+/// U and V planes must have the size given by [`ChromaSubsampling::chroma_size`] for `config.chroma_subsampling`.
+/// Alpha is always full-resolution and uses full range.
+///
+/// returns AVIF file with size of color and alpha data
+pub fn encode_raw_planes(width: usize, height: usize, y_plane: &[u8], u_plane: &[u8], v_plane: &[u8], a_plane: Option<&[u8]>, color_pixel_range: PixelRange, config: &EncConfig) -> Result<EncodedImage, Error> {
+    check_planes(width, height, y_plane, u_plane, v_plane, a_plane, config.chroma_subsampling)?;
+    config.limits.check(width, height, config.bit_depth, false, 0)?;
+    config.validate()?;
+    let depth = config.bit_depth;
+    if depth > 8 {
+        let color_full_range = color_pixel_range == PixelRange::Full;
+        let (chroma_width, chroma_height) = config.chroma_subsampling.chroma_size(width, height);
+        let upscaled = |plane: &[u8], len, full_range| -> Vec<u16> {
+            plane[..len].iter().map(|&s| upscale(s, depth, full_range)).collect()
+        };
+        let y_plane = upscaled(y_plane, width * height, color_full_range);
+        let u_plane = upscaled(u_plane, chroma_width * chroma_height, color_full_range);
+        let v_plane = upscaled(v_plane, chroma_width * chroma_height, color_full_range);
+        let a_plane = a_plane.map(|a| upscaled(a, width * height, true));
+        return encode_planes(width, height, &y_plane, &u_plane, &v_plane, a_plane.as_deref(), color_pixel_range, depth, config);
+    }
+    encode_planes(width, height, y_plane, u_plane, v_plane, a_plane, color_pixel_range, depth, config)
+}
+
+/// Same as [`encode_raw_planes`], but takes samples with `config.bit_depth` bits (typically 10 or 12), without scaling them.
+///
+/// Samples must not be larger than the maximum value of `config.bit_depth`.
+///
+/// returns AVIF file with size of color and alpha data
+#[allow(clippy::too_many_arguments)]
+pub fn encode_raw_planes_u16(width: usize, height: usize, y_plane: &[u16], u_plane: &[u16], v_plane: &[u16], a_plane: Option<&[u16]>, color_pixel_range: PixelRange, config: &EncConfig) -> Result<EncodedImage, Error> {
+    check_planes(width, height, y_plane, u_plane, v_plane, a_plane, config.chroma_subsampling)?;
+    config.limits.check(width, height, config.bit_depth, false, 0)?;
+    config.validate()?;
+    let depth = config.bit_depth;
+    encode_planes(width, height, y_plane, u_plane, v_plane, a_plane, color_pixel_range, depth, config)
+}
+
+fn check_planes<S>(width: usize, height: usize, y_plane: &[S], u_plane: &[S], v_plane: &[S], a_plane: Option<&[S]>, subsampling: ChromaSubsampling) -> Result<(), Error> {
+    let (chroma_width, chroma_height) = subsampling.chroma_size(width, height);
+    if y_plane.len() < width * height || u_plane.len() < chroma_width * chroma_height || v_plane.len() < chroma_width * chroma_height ||
+        a_plane.map_or(false, |a| a.len() < width * height) {
+        return Err(Error::TooFewPixels);
     }
+    Ok(())
+}
 
+/// Samples must already have `depth` bits, and chroma must be subsampled according to the config
+#[allow(clippy::too_many_arguments)]
+fn encode_planes<S: Pixel>(width: usize, height: usize, y_plane: &[S], u_plane: &[S], v_plane: &[S], a_plane: Option<&[S]>, color_pixel_range: PixelRange, depth: u8, config: &EncConfig) -> Result<EncodedImage, Error> {
     // quality setting
     let quantizer = quality_to_quantizer(config.quality);
     let alpha_quantizer = quality_to_quantizer(config.alpha_quality);
 
     let matrix_coefficients = match config.color_space {
-        ColorSpace::YCbCr => MatrixCoefficients::BT709,
-        ColorSpace::RGB => MatrixCoefficients::Identity,
+        ColorSpace::YCbCr => config.matrix_coefficients.av1(),
+        ColorSpace::RGB => rav1e::prelude::MatrixCoefficients::Identity,
     };
 
     let color_description = Some(ColorDescription {
-        transfer_characteristics: TransferCharacteristics::SRGB,
-        color_primaries: ColorPrimaries::BT709, // sRGB-compatible
+        transfer_characteristics: config.transfer_characteristics.av1(),
+        color_primaries: config.color_primaries.av1(),
         matrix_coefficients,
     });
 
     let threads = if config.threads > 0 { config.threads } else { num_cpus::get() };
 
+    // If one of the channels fails, the other one is aborted too
+    let failed = AtomicBool::new(false);
+    let cancelled = || failed.load(Ordering::Relaxed) || config.cancel.as_ref().map_or(false, |c| c.is_cancelled());
+    if cancelled() {
+        return Err(Error::Cancelled);
+    }
+
+    // rav1e doesn't report progress within a frame, so progress is counted in planes encoded
+    let planes_total = if a_plane.is_some() { 4 } else { 3 };
+    let planes_done = AtomicUsize::new(0);
+    let report_planes_done = |planes: usize| if let Some(progress) = &config.progress {
+        let done = planes_done.fetch_add(planes, Ordering::Relaxed) + planes;
+        progress.report(done as f32 / (planes_total + 1) as f32);
+    };
+    if let Some(progress) = &config.progress {
+        progress.report(0.);
+    }
+
     // Firefox 81 doesn't support Full yet, but doesn't support alpha either
     let (color, alpha) = rayon::join(
-        || encode_to_av1(&Av1EncodeConfig {
+        || {
+            let res = timed(|| encode_to_av1(&Av1EncodeConfig {
                 width,
                 height,
                 planes: &[&y_plane, &u_plane, &v_plane],
                 quantizer,
-                speed: SpeedTweaks::from_my_preset(config.speed, config.quality as _),
+                speed: config.speed_tweaks_for(config.quality),
                 threads,
+                bit_depth: depth,
                 pixel_range: color_pixel_range,
-                chroma_sampling: ChromaSampling::Cs444,
+                chroma_sampling: config.chroma_subsampling.rav1e_sampling(),
                 color_description,
-            }),
+                cancelled: &cancelled,
+            }));
+            if res.0.is_ok() { report_planes_done(3); } else { failed.store(true, Ordering::Relaxed); }
+            res
+        },
         || if let Some(a_plane) = a_plane {
-            Some(encode_to_av1(&Av1EncodeConfig {
+            let res = timed(|| encode_to_av1(&Av1EncodeConfig {
                 width,
                 height,
                 planes: &[&a_plane],
                 quantizer: alpha_quantizer,
-                speed: SpeedTweaks::from_my_preset(config.speed, config.alpha_quality as _),
+                speed: config.speed_tweaks_for(config.alpha_quality),
                 threads,
+                bit_depth: depth,
                 pixel_range: PixelRange::Full,
                 chroma_sampling: ChromaSampling::Cs400,
                 color_description: None,
-            }))
+                cancelled: &cancelled,
+            }));
+            if res.0.is_ok() { report_planes_done(1); } else { failed.store(true, Ordering::Relaxed); }
+            Some(res)
           } else {
             None
         });
-    let (color, alpha) = (color?, alpha.transpose()?);
+    let (color, color_encode_time) = color;
+    let (alpha, alpha_encode_time) = alpha.map_or((None, Duration::default()), |(a, t)| (Some(a), t));
+    let (color, alpha) = match (color, alpha.transpose()) {
+        (Ok(color), Ok(alpha)) => (color, alpha),
+        // the aborted channel reports cancellation, but the error that caused it is more relevant
+        (Err(Error::Cancelled), Err(err)) | (Err(err), _) | (_, Err(err)) => return Err(err),
+    };
+    if config.cancel.as_ref().map_or(false, |c| c.is_cancelled()) {
+        return Err(Error::Cancelled);
+    }
 
+    // The colr box must agree with the AV1 sequence header
     let out = avif_serialize::Aviffy::new()
+        .matrix_coefficients(match config.color_space {
+            ColorSpace::YCbCr => config.matrix_coefficients.avif(),
+            ColorSpace::RGB => avif_serialize::constants::MatrixCoefficients::Rgb,
+        })
+        .color_primaries(config.color_primaries.avif())
+        .transfer_characteristics(config.transfer_characteristics.avif())
+        .full_color_range(color_pixel_range == PixelRange::Full)
         .premultiplied_alpha(config.premultiplied_alpha)
-        .to_vec(&color, alpha.as_deref(), width as u32, height as u32, 8);
+        .set_chroma_subsampling(config.chroma_subsampling.is_subsampled())
+        .set_seq_profile(config.chroma_subsampling.seq_profile())
+        .to_vec(&color, alpha.as_deref(), width as u32, height as u32, depth);
     let color_size = color.len();
     let alpha_size = alpha.as_ref().map_or(0, |a| a.len());
 
-    Ok((out, color_size, alpha_size))
+    if let Some(progress) = &config.progress {
+        progress.report(1.);
+    }
+
+    Ok(EncodedImage {
+        avif_file: out,
+        color_byte_size: color_size,
+        alpha_byte_size: alpha_size,
+        color_encode_time,
+        alpha_encode_time,
+    })
+}
+
+fn timed<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    let start = Instant::now();
+    let res = f();
+    (res, start.elapsed())
 }
 
 fn quality_to_quantizer(quality: f32) -> usize {
@@ -206,6 +555,9 @@ fn quality_to_quantizer(quality: f32) -> usize {
 }
 
 
+/// Fine-tuning of rav1e's speed settings. `None` keeps rav1e's default for the preset.
+///
+/// See [`SpeedTweakOverrides`]
 #[derive(Debug, Copy, Clone)]
 pub struct SpeedTweaks {
     pub speed_preset: u8,
@@ -282,6 +634,22 @@ impl SpeedTweaks {
         }
     }
 
+    fn validate(&self) -> Result<(), Error> {
+        if self.speed_preset > 10 {
+            return Err(Error::UnsupportedConfig("Speed preset must be between 0 and 10".into()));
+        }
+        if self.min_tile_size == 0 {
+            return Err(Error::UnsupportedConfig("Minimum tile size must be non-zero".into()));
+        }
+        if let Some((min, max)) = self.partition_range {
+            if block_size(min).is_none() || block_size(max).is_none() || min > max {
+                return Err(Error::UnsupportedConfig(format!("Partition range must be two sizes out of 4, 8, 16, 32, 64 or 128, smallest first, not ({}, {})", min, max)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Settings must have been validated
     pub(crate) fn speed_settings(&self) -> SpeedSettings {
 
         let mut speed_settings = SpeedSettings::from_preset(self.speed_preset.into());
@@ -304,43 +672,99 @@ impl SpeedTweaks {
         if let Some(v) = self.use_satd_subpel { speed_settings.use_satd_subpel = v; }
         if let Some(v) = self.fine_directional_intra { speed_settings.fine_directional_intra = v; }
         if let Some(v) = self.complex_prediction_modes { speed_settings.prediction_modes = if v { PredictionModesSetting::ComplexAll } else { PredictionModesSetting::Simple} };
-        if let Some((min, max)) = self.partition_range {
-            assert!(min <= max);
-            fn sz(s: u8) -> BlockSize {
-                match s {
-                    4 => BlockSize::BLOCK_4X4,
-                    8 => BlockSize::BLOCK_8X8,
-                    16 => BlockSize::BLOCK_16X16,
-                    32 => BlockSize::BLOCK_32X32,
-                    64 => BlockSize::BLOCK_64X64,
-                    128 => BlockSize::BLOCK_128X128,
-                    _ => panic!("bad size {}", s),
-                }
-            }
-            speed_settings.partition_range = PartitionRange::new(sz(min), sz(max));
+        if let Some((Some(min), Some(max))) = self.partition_range.map(|(min, max)| (block_size(min), block_size(max))) {
+            speed_settings.partition_range = PartitionRange::new(min, max);
         }
 
         speed_settings
     }
 }
 
-pub(crate) struct Av1EncodeConfig<'a> {
+/// Changes to the [`SpeedTweaks`] chosen for the speed and quality of the [`Config`](crate::Config).
+/// `None` keeps the chosen value.
+///
+/// See [`Config::speed_tweaks`](crate::Config::speed_tweaks)
+#[derive(Debug, Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default, deny_unknown_fields))]
+pub struct SpeedTweakOverrides {
+    pub speed_preset: Option<u8>,
+
+    pub fast_deblock: Option<bool>,
+    pub reduced_tx_set: Option<bool>,
+    pub tx_domain_distortion: Option<bool>,
+    pub tx_domain_rate: Option<bool>,
+    pub encode_bottomup: Option<bool>,
+    pub rdo_tx_decision: Option<bool>,
+    pub cdef: Option<bool>,
+    /// loop restoration filter
+    pub lrf: Option<bool>,
+    pub non_square_partition: Option<bool>,
+    pub sgr_complexity_full: Option<bool>,
+    pub use_satd_subpel: Option<bool>,
+    pub inter_tx_split: Option<bool>,
+    pub fine_directional_intra: Option<bool>,
+    pub complex_prediction_modes: Option<bool>,
+    pub partition_range: Option<(u8, u8)>,
+    pub min_tile_size: Option<u16>,
+}
+
+impl SpeedTweakOverrides {
+    /// `tweaks` with the fields that are set here replaced
+    pub fn apply(&self, tweaks: SpeedTweaks) -> SpeedTweaks {
+        SpeedTweaks {
+            speed_preset: self.speed_preset.unwrap_or(tweaks.speed_preset),
+            fast_deblock: self.fast_deblock.or(tweaks.fast_deblock),
+            reduced_tx_set: self.reduced_tx_set.or(tweaks.reduced_tx_set),
+            tx_domain_distortion: self.tx_domain_distortion.or(tweaks.tx_domain_distortion),
+            tx_domain_rate: self.tx_domain_rate.or(tweaks.tx_domain_rate),
+            encode_bottomup: self.encode_bottomup.or(tweaks.encode_bottomup),
+            rdo_tx_decision: self.rdo_tx_decision.or(tweaks.rdo_tx_decision),
+            cdef: self.cdef.or(tweaks.cdef),
+            lrf: self.lrf.or(tweaks.lrf),
+            non_square_partition: self.non_square_partition.or(tweaks.non_square_partition),
+            sgr_complexity_full: self.sgr_complexity_full.or(tweaks.sgr_complexity_full),
+            use_satd_subpel: self.use_satd_subpel.or(tweaks.use_satd_subpel),
+            inter_tx_split: self.inter_tx_split.or(tweaks.inter_tx_split),
+            fine_directional_intra: self.fine_directional_intra.or(tweaks.fine_directional_intra),
+            complex_prediction_modes: self.complex_prediction_modes.or(tweaks.complex_prediction_modes),
+            partition_range: self.partition_range.or(tweaks.partition_range),
+            min_tile_size: self.min_tile_size.unwrap_or(tweaks.min_tile_size),
+        }
+    }
+}
+
+/// Square block of `size` pixels
+fn block_size(size: u8) -> Option<BlockSize> {
+    Some(match size {
+        4 => BlockSize::BLOCK_4X4,
+        8 => BlockSize::BLOCK_8X8,
+        16 => BlockSize::BLOCK_16X16,
+        32 => BlockSize::BLOCK_32X32,
+        64 => BlockSize::BLOCK_64X64,
+        128 => BlockSize::BLOCK_128X128,
+        _ => return None,
+    })
+}
+
+pub(crate) struct Av1EncodeConfig<'a, S> {
     pub width: usize,
     pub height: usize,
-    pub planes: &'a [&'a [u8]],
+    pub planes: &'a [&'a [S]],
     pub quantizer: usize,
     pub speed: SpeedTweaks,
     pub threads: usize,
+    pub bit_depth: u8,
     pub pixel_range: PixelRange,
     pub chroma_sampling: ChromaSampling,
     pub color_description: Option<ColorDescription>,
+    pub cancelled: &'a (dyn Fn() -> bool + Sync),
 }
 
-fn encode_to_av1(p: &Av1EncodeConfig<'_>) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+fn encode_to_av1<S: Pixel>(p: &Av1EncodeConfig<'_, S>) -> Result<Vec<u8>, Error> {
     // AV1 needs all the CPU power you can give it,
     // except when it'd create inefficiently tiny tiles
     let tiles = p.threads.min((p.width * p.height) / (p.speed.min_tile_size as usize).pow(2));
-    let bit_depth = 8;
+    let bit_depth = p.bit_depth.into();
 
     let speed_settings = p.speed.speed_settings();
     let cfg = Config::new()
@@ -376,18 +800,82 @@ fn encode_to_av1(p: &Av1EncodeConfig<'_>) -> Result<Vec<u8>, Box<dyn std::error:
         speed_settings,
     });
 
-    let mut ctx: Context<u8> = cfg.new_context()?;
+    if (p.cancelled)() {
+        return Err(Error::Cancelled);
+    }
+
+    // rav1e needs u16 pixels for depths over 8 bits
+    if bit_depth > 8 {
+        encode_frame::<u16, S>(&cfg, p)
+    } else {
+        encode_frame::<u8, S>(&cfg, p)
+    }
+}
+
+fn encode_frame<P: Pixel, S: Pixel>(cfg: &Config, p: &Av1EncodeConfig<'_, S>) -> Result<Vec<u8>, Error> {
+    let mut ctx: Context<P> = cfg.new_context()?;
     let mut frame = ctx.new_frame();
 
     for (dst, src) in frame.planes.iter_mut().zip(p.planes) {
-        dst.copy_from_raw_u8(src, p.width, (bit_depth+7)/8);
+        // chroma planes may be smaller. `dst.cfg.width` can't be used, because it may be padded.
+        let plane_width = (p.width + dst.cfg.xdec) >> dst.cfg.xdec;
+        let mut dst = dst.mut_slice(Default::default());
+        for (dst_row, src_row) in dst.rows_iter_mut().zip(src.chunks_exact(plane_width)) {
+            for (dst_px, &src_px) in dst_row[..plane_width].iter_mut().zip(src_row) {
+                let src_px: u32 = src_px.into();
+                *dst_px = P::cast_from(src_px);
+            }
+        }
     }
 
     ctx.send_frame(frame)?;
     ctx.flush();
 
+    receive_packets(ctx, p.cancelled)
+}
+
+/// How often a cancelled flag is checked while rav1e is encoding a frame
+#[cfg(not(target_arch = "wasm32"))]
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// rav1e can't be interrupted while it encodes a frame, so it encodes on its own thread.
+/// When cancelled, this returns without waiting for it, and the frame is discarded when it's done.
+#[cfg(not(target_arch = "wasm32"))]
+fn receive_packets<P: Pixel>(mut ctx: Context<P>, cancelled: &(dyn Fn() -> bool + Sync)) -> Result<Vec<u8>, Error> {
+    use std::sync::mpsc::{self, RecvTimeoutError};
+
+    let (sender, receiver) = mpsc::channel();
+    let encoder = std::thread::Builder::new()
+        .name("ravif-frame".into())
+        .spawn(move || {
+            let _ = sender.send(receive_packets_until(&mut ctx, &|| false));
+        })
+        .map_err(|e| Error::EncodingError(Box::new(e)))?;
+    loop {
+        match receiver.recv_timeout(CANCEL_POLL_INTERVAL) {
+            Ok(res) => return res,
+            Err(RecvTimeoutError::Timeout) => if cancelled() {
+                return Err(Error::Cancelled);
+            },
+            Err(RecvTimeoutError::Disconnected) => match encoder.join() {
+                Err(panic) => std::panic::resume_unwind(panic),
+                Ok(()) => unreachable!(),
+            },
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn receive_packets<P: Pixel>(mut ctx: Context<P>, cancelled: &(dyn Fn() -> bool + Sync)) -> Result<Vec<u8>, Error> {
+    receive_packets_until(&mut ctx, cancelled)
+}
+
+fn receive_packets_until<P: Pixel>(ctx: &mut Context<P>, cancelled: &dyn Fn() -> bool) -> Result<Vec<u8>, Error> {
     let mut out = Vec::new();
     loop {
+        if cancelled() {
+            return Err(Error::Cancelled);
+        }
         match ctx.receive_packet() {
             Ok(mut packet) => match packet.frame_type {
                 FrameType::KEY => {
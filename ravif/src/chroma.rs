@@ -0,0 +1,101 @@
+use rav1e::prelude::*;
+use std::iter::Sum;
+
+/// Resolution of the color (chroma) planes relative to the brightness (luma) plane
+///
+/// See [`Config::chroma_subsampling`](crate::Config::chroma_subsampling)
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChromaSubsampling {
+    /// Full resolution color. Best quality.
+    Yuv444,
+    /// Half horizontal resolution of color
+    Yuv422,
+    /// Half horizontal and vertical resolution of color. Smaller files, and supported by all AVIF decoders.
+    Yuv420,
+}
+
+named_values!(ChromaSubsampling, "Chroma subsampling" {
+    Yuv444 => "444",
+    Yuv422 => "422",
+    Yuv420 => "420",
+});
+
+impl ChromaSubsampling {
+    /// Width and height of the chroma planes of an image. Odd sizes are rounded up.
+    pub fn chroma_size(self, width: usize, height: usize) -> (usize, usize) {
+        let (x, y) = self.shifts();
+        ((width + x) >> x, (height + y) >> y)
+    }
+
+    /// log2 of the horizontal and vertical subsampling factors
+    pub(crate) fn shifts(self) -> (usize, usize) {
+        match self {
+            Self::Yuv444 => (0, 0),
+            Self::Yuv422 => (1, 0),
+            Self::Yuv420 => (1, 1),
+        }
+    }
+
+    /// Whether width and height are subsampled, as needed for the `av1C` box
+    pub(crate) fn is_subsampled(self) -> (bool, bool) {
+        let (x, y) = self.shifts();
+        (x > 0, y > 0)
+    }
+
+    pub(crate) fn rav1e_sampling(self) -> ChromaSampling {
+        match self {
+            Self::Yuv444 => ChromaSampling::Cs444,
+            Self::Yuv422 => ChromaSampling::Cs422,
+            Self::Yuv420 => ChromaSampling::Cs420,
+        }
+    }
+
+    /// AV1 profile required for this subsampling (12-bit depth needs profile 2 regardless)
+    pub(crate) fn seq_profile(self) -> u8 {
+        match self {
+            Self::Yuv420 => 0,
+            Self::Yuv444 => 1,
+            Self::Yuv422 => 2,
+        }
+    }
+}
+
+/// Reduce a full-resolution chroma plane to the subsampled size by averaging 2x1 or 2x2 blocks of samples.
+///
+/// Blocks at the right and bottom edges of odd-sized images average only the samples that exist.
+pub(crate) fn downsample<P: Pixel>(plane: &[P], width: usize, height: usize, subsampling: ChromaSubsampling) -> Vec<P> {
+    box_average(plane, width, height, subsampling, |sum: u32, count| P::cast_from((sum + count / 2) / count))
+}
+
+/// Same as [`downsample`], with `average(sum, count)` computing each output sample from the sum of a block
+pub(crate) fn box_average<T: Copy + Into<S>, S: Sum>(plane: &[T], width: usize, height: usize, subsampling: ChromaSubsampling, average: impl Fn(S, u32) -> T) -> Vec<T> {
+    let (x_shift, y_shift) = subsampling.shifts();
+    let (chroma_width, chroma_height) = subsampling.chroma_size(width, height);
+    let mut out = Vec::with_capacity(chroma_width * chroma_height);
+    for cy in 0..chroma_height {
+        let rows = (cy << y_shift)..((cy + 1) << y_shift).min(height);
+        for cx in 0..chroma_width {
+            let cols = (cx << x_shift)..((cx + 1) << x_shift).min(width);
+            let count = (rows.len() * cols.len()) as u32;
+            let sum = rows.clone().flat_map(|y| &plane[y * width + cols.start..y * width + cols.end]).map(|&sample| sample.into()).sum();
+            out.push(average(sum, count));
+        }
+    }
+    out
+}
+
+#[test]
+fn odd_sizes() {
+    assert_eq!((3, 2), ChromaSubsampling::Yuv420.chroma_size(5, 3));
+    assert_eq!((3, 3), ChromaSubsampling::Yuv422.chroma_size(5, 3));
+    assert_eq!((5, 3), ChromaSubsampling::Yuv444.chroma_size(5, 3));
+
+    let plane: Vec<u8> = vec![
+        10, 20, 30, 40, 50,
+        30, 40, 50, 60, 70,
+        90, 90, 0, 1, 255,
+    ];
+    assert_eq!(vec![25, 45, 60, 90, 1, 255], downsample(&plane, 5, 3, ChromaSubsampling::Yuv420));
+    assert_eq!(vec![15, 35, 50, 35, 55, 70, 90, 1, 255], downsample(&plane, 5, 3, ChromaSubsampling::Yuv422));
+}
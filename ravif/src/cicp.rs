@@ -0,0 +1,198 @@
+//! Color description written to the AV1 sequence header and the `colr` box of AVIF files
+//! (coding-independent code points from ITU-T H.273)
+//!
+//! Pixels are not converted between primaries or transfer functions. These settings only describe
+//! what the pixels already are, except the matrix, which is used to convert RGB to YCbCr.
+
+use avif_serialize::constants as avif;
+use rav1e::prelude as av1;
+
+/// How RGB is converted to luma and chroma with [`ColorSpace::YCbCr`](crate::ColorSpace::YCbCr)
+///
+/// See [`Config::matrix_coefficients`](crate::Config::matrix_coefficients)
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MatrixCoefficients {
+    /// ITU-R BT.709. The default, and a good match for sRGB images.
+    Bt709,
+    /// ITU-R BT.601, as used by JPEG
+    Bt601,
+    /// ITU-R BT.2020 non-constant luminance, for wide-gamut images
+    Bt2020Ncl,
+    /// YCgCo, which is cheap to compute and reversible
+    YCgCo,
+}
+
+/// Chromaticity of the red, green and blue primaries and the white point
+///
+/// See [`Config::color_primaries`](crate::Config::color_primaries)
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorPrimaries {
+    /// ITU-R BT.709, same as sRGB. The default.
+    Bt709,
+    /// ITU-R BT.601 (525 lines)
+    Bt601,
+    /// ITU-R BT.2020 and BT.2100
+    Bt2020,
+    /// Display P3 (SMPTE EG 432-1), used by wide-gamut Apple devices
+    DisplayP3,
+}
+
+/// Transfer function (gamma) of the pixel values
+///
+/// See [`Config::transfer_characteristics`](crate::Config::transfer_characteristics)
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TransferCharacteristics {
+    /// IEC 61966-2-1 sRGB. The default.
+    Srgb,
+    /// ITU-R BT.709
+    Bt709,
+    /// Linear light
+    Linear,
+    /// SMPTE ST 2084 (BT.2100 PQ) for HDR. Use it with a bit depth higher than 8.
+    Pq,
+    /// BT.2100 HLG (Hybrid Log-Gamma) for HDR. Use it with a bit depth higher than 8.
+    Hlg,
+}
+
+named_values!(MatrixCoefficients, "Matrix coefficients" {
+    Bt709 => "bt709",
+    Bt601 => "bt601",
+    Bt2020Ncl => "bt2020ncl",
+    YCgCo => "ycgco",
+});
+
+named_values!(ColorPrimaries, "Color primaries" {
+    Bt709 => "bt709",
+    Bt601 => "bt601",
+    Bt2020 => "bt2020",
+    DisplayP3 => "displayp3",
+});
+
+named_values!(TransferCharacteristics, "Transfer characteristics" {
+    Srgb => "srgb",
+    Bt709 => "bt709",
+    Linear => "linear",
+    Pq => "pq",
+    Hlg => "hlg",
+});
+
+impl MatrixCoefficients {
+    pub(crate) fn av1(self) -> av1::MatrixCoefficients {
+        match self {
+            Self::Bt709 => av1::MatrixCoefficients::BT709,
+            Self::Bt601 => av1::MatrixCoefficients::BT601,
+            Self::Bt2020Ncl => av1::MatrixCoefficients::BT2020NCL,
+            Self::YCgCo => av1::MatrixCoefficients::YCgCo,
+        }
+    }
+
+    pub(crate) fn avif(self) -> avif::MatrixCoefficients {
+        match self {
+            Self::Bt709 => avif::MatrixCoefficients::Bt709,
+            Self::Bt601 => avif::MatrixCoefficients::Bt601,
+            Self::Bt2020Ncl => avif::MatrixCoefficients::Bt2020Ncl,
+            Self::YCgCo => avif::MatrixCoefficients::Ycgco,
+        }
+    }
+
+    /// Luma weights of red and blue, if the matrix is a YCbCr one
+    fn kr_kb(self) -> Option<(f32, f32)> {
+        match self {
+            Self::Bt709 => Some((0.2126, 0.0722)),
+            Self::Bt601 => Some((0.299, 0.114)),
+            Self::Bt2020Ncl => Some((0.2627, 0.0593)),
+            Self::YCgCo => None,
+        }
+    }
+
+    /// Y (0..=1), and Cb and Cr (-0.5..=0.5) from RGB in 0..=1 range. For YCgCo, Cb is Cg and Cr is Co.
+    pub(crate) fn rgb_to_ycbcr(self, [r, g, b]: [f32; 3]) -> [f32; 3] {
+        match self.kr_kb() {
+            Some((kr, kb)) => {
+                let y = kr * r + (1. - kr - kb) * g + kb * b;
+                [y, (b - y) * (0.5 / (1. - kb)), (r - y) * (0.5 / (1. - kr))]
+            },
+            None => [
+                0.25 * r + 0.5 * g + 0.25 * b,
+                -0.25 * r + 0.5 * g - 0.25 * b,
+                0.5 * r - 0.5 * b,
+            ],
+        }
+    }
+
+    /// Inverse of [`rgb_to_ycbcr`](Self::rgb_to_ycbcr), without clamping
+    pub(crate) fn ycbcr_to_rgb(self, [y, cb, cr]: [f32; 3]) -> [f32; 3] {
+        match self.kr_kb() {
+            Some((kr, kb)) => {
+                let r = y + cr * (2. * (1. - kr));
+                let b = y + cb * (2. * (1. - kb));
+                let g = (y - kr * r - kb * b) / (1. - kr - kb);
+                [r, g, b]
+            },
+            None => {
+                let tmp = y - cb;
+                [tmp + cr, y + cb, tmp - cr]
+            },
+        }
+    }
+}
+
+impl ColorPrimaries {
+    pub(crate) fn av1(self) -> av1::ColorPrimaries {
+        match self {
+            Self::Bt709 => av1::ColorPrimaries::BT709,
+            Self::Bt601 => av1::ColorPrimaries::BT601,
+            Self::Bt2020 => av1::ColorPrimaries::BT2020,
+            Self::DisplayP3 => av1::ColorPrimaries::SMPTE432,
+        }
+    }
+
+    pub(crate) fn avif(self) -> avif::ColorPrimaries {
+        match self {
+            Self::Bt709 => avif::ColorPrimaries::Bt709,
+            Self::Bt601 => avif::ColorPrimaries::Bt601,
+            Self::Bt2020 => avif::ColorPrimaries::Bt2020,
+            Self::DisplayP3 => avif::ColorPrimaries::DisplayP3,
+        }
+    }
+}
+
+impl TransferCharacteristics {
+    pub(crate) fn av1(self) -> av1::TransferCharacteristics {
+        match self {
+            Self::Srgb => av1::TransferCharacteristics::SRGB,
+            Self::Bt709 => av1::TransferCharacteristics::BT709,
+            Self::Linear => av1::TransferCharacteristics::Linear,
+            Self::Pq => av1::TransferCharacteristics::SMPTE2084,
+            Self::Hlg => av1::TransferCharacteristics::HLG,
+        }
+    }
+
+    pub(crate) fn avif(self) -> avif::TransferCharacteristics {
+        match self {
+            Self::Srgb => avif::TransferCharacteristics::Srgb,
+            Self::Bt709 => avif::TransferCharacteristics::Bt709,
+            Self::Linear => avif::TransferCharacteristics::Linear,
+            Self::Pq => avif::TransferCharacteristics::Smpte2084,
+            Self::Hlg => avif::TransferCharacteristics::Hlg,
+        }
+    }
+}
+
+#[test]
+fn round_trip() {
+    for matrix in [MatrixCoefficients::Bt709, MatrixCoefficients::Bt601, MatrixCoefficients::Bt2020Ncl, MatrixCoefficients::YCgCo] {
+        for rgb in [[0., 0., 0.], [1., 1., 1.], [1., 0., 0.], [0.2, 0.9, 0.4], [0., 0., 1.]] {
+            let [y, cb, cr] = matrix.rgb_to_ycbcr(rgb);
+            assert!((0. ..=1.).contains(&y) && cb.abs() <= 0.5 && cr.abs() <= 0.5, "{:?} {:?}", matrix, rgb);
+            let back = matrix.ycbcr_to_rgb([y, cb, cr]);
+            assert!((0..3).all(|c| (back[c] - rgb[c]).abs() < 1e-5), "{:?} {:?} {:?}", matrix, rgb, back);
+        }
+        // gray has no chroma
+        let [_, cb, cr] = matrix.rgb_to_ycbcr([0.5, 0.5, 0.5]);
+        assert!(cb.abs() < 1e-6 && cr.abs() < 1e-6);
+    }
+}
@@ -2,6 +2,7 @@ use imgref::Img;
 use imgref::ImgRef;
 use rgb::ComponentMap;
 use rgb::RGB;
+use rgb::RGBA16;
 use rgb::RGBA8;
 
 #[inline]
@@ -45,6 +46,24 @@ pub fn cleared_alpha(mut img: Img<Vec<RGBA8>>) -> Img<Vec<RGBA8>> {
     blur_transparent_pixels(img2.as_ref())
 }
 
+/// Same as [`cleared_alpha`], but for 16-bit pixels
+///
+/// Only fully-transparent pixels are changed. Their new colors are computed at 8-bit precision,
+/// which doesn't matter, because they're invisible.
+pub fn cleared_alpha16(mut img: Img<Vec<RGBA16>>) -> Img<Vec<RGBA16>> {
+    if img.pixels().all(|px| px.a != 0) {
+        return img;
+    }
+    let img8 = Img::new(img.pixels().map(|px| px.map(|c| (c >> 8) as u8)).collect(), img.width(), img.height());
+    let cleared = cleared_alpha(img8);
+    for (px, cleared_px) in img.pixels_mut().zip(cleared.pixels()) {
+        if px.a == 0 {
+            *px = cleared_px.map(|c| u16::from(c) * 257);
+        }
+    }
+    img
+}
+
 /// copy color from opaque pixels to transparent pixels
 /// (so that when edges get crushed by compression, the distortion will be away from visible edge)
 fn bleed_opaque_color(img: ImgRef<RGBA8>) -> Img<Vec<RGBA8>> {
@@ -0,0 +1,51 @@
+use rav1e::prelude::*;
+use std::fmt;
+
+/// Errors returned by the encoding functions
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The image buffer or one of the planes is smaller than `width * height`
+    TooFewPixels,
+    /// rav1e rejected the configuration or failed to encode the image
+    EncodingError(Box<dyn std::error::Error + Send + Sync>),
+    /// Encoding has been stopped with [`CancelFlag`](crate::CancelFlag)
+    Cancelled,
+    /// The image is larger than allowed by [`Limits`](crate::Limits)
+    LimitExceeded(String),
+    /// A setting out of its valid range, or a combination of settings that can't be encoded
+    UnsupportedConfig(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooFewPixels => f.write_str("Too few pixels"),
+            Self::EncodingError(_) => f.write_str("Encoding error"),
+            Self::Cancelled => f.write_str("Encoding cancelled"),
+            Self::LimitExceeded(msg) => f.write_str(msg),
+            Self::UnsupportedConfig(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::EncodingError(e) => Some(&**e),
+            _ => None,
+        }
+    }
+}
+
+impl From<EncoderStatus> for Error {
+    fn from(status: EncoderStatus) -> Self {
+        Self::EncodingError(Box::new(status))
+    }
+}
+
+impl From<InvalidConfig> for Error {
+    fn from(err: InvalidConfig) -> Self {
+        Self::EncodingError(Box::new(err))
+    }
+}
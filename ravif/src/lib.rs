@@ -1,13 +1,45 @@
+#[macro_use]
+mod names;
+
 mod av1encoder;
 pub use av1encoder::encode_raw_planes;
+pub use av1encoder::encode_raw_planes_u16;
 pub use av1encoder::encode_rgb;
 pub use av1encoder::encode_rgba;
+pub use av1encoder::encode_rgba16;
+pub use av1encoder::encode_rgba_pixels;
 pub use av1encoder::ColorSpace;
 pub use av1encoder::EncConfig as Config;
+pub use av1encoder::EncodedImage;
+pub use av1encoder::SpeedTweakOverrides;
+pub use av1encoder::SpeedTweaks;
+
+mod chroma;
+pub use chroma::ChromaSubsampling;
+
+mod cicp;
+pub use cicp::ColorPrimaries;
+pub use cicp::MatrixCoefficients;
+pub use cicp::TransferCharacteristics;
+
+mod sharpyuv;
+
+mod error;
+pub use error::Error;
+
+mod limits;
+pub use limits::Limits;
+
+mod progress;
+pub use progress::CancelFlag;
+pub use progress::ProgressCallback;
 
 mod dirtyalpha;
 pub use dirtyalpha::cleared_alpha;
+pub use dirtyalpha::cleared_alpha16;
 
 pub use imgref::Img;
+pub use rav1e::prelude::PixelRange;
 pub use rgb::RGB8;
+pub use rgb::RGBA16;
 pub use rgb::RGBA8;
@@ -0,0 +1,68 @@
+use crate::error::Error;
+
+/// Approximate peak memory used by ravif and rav1e per pixel of an 8-bit image.
+///
+/// rav1e keeps several copies of every plane (source, reconstruction, reference frames),
+/// separately for the color and alpha channels. With a bit depth over 8 all of them use `u16` samples,
+/// which doubles this.
+const ENCODER_BYTES_PER_PIXEL: usize = 32;
+
+/// Additional memory per pixel for [`Config::sharp_yuv`](crate::Config::sharp_yuv): the f32 RGB copy of the image,
+/// and the full-resolution f32 luma, upsampled chroma and chroma error planes it works on.
+const SHARP_YUV_BYTES_PER_PIXEL: usize = 36;
+
+/// Maximum image size accepted by the encoder, as protection against images that would
+/// exhaust memory (such as "decompression bombs")
+///
+/// `None` means no limit. There are no limits by default.
+#[derive(Debug, Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default, deny_unknown_fields))]
+pub struct Limits {
+    pub max_width: Option<usize>,
+    pub max_height: Option<usize>,
+    /// Maximum `width * height`
+    pub max_pixels: Option<usize>,
+    /// Maximum approximate peak memory in bytes
+    pub max_memory: Option<usize>,
+}
+
+impl Limits {
+    /// Check an image size before allocating anything for it.
+    ///
+    /// `bit_depth` is the depth the image will be encoded with ([`Config::bit_depth`](crate::Config::bit_depth)).
+    /// `sharp_yuv` is whether the encoder will convert RGB with [`Config::sharp_yuv`](crate::Config::sharp_yuv)
+    /// (see [`Config::uses_sharp_yuv`](crate::Config::uses_sharp_yuv)).
+    /// `other_memory` is memory needed in addition to the encoder's own, e.g. for the decoded image.
+    pub fn check(&self, width: usize, height: usize, bit_depth: u8, sharp_yuv: bool, other_memory: usize) -> Result<(), Error> {
+        if let Some(max) = self.max_width {
+            if width > max {
+                return Err(Error::LimitExceeded(format!("Image width {} is over the limit of {}", width, max)));
+            }
+        }
+        if let Some(max) = self.max_height {
+            if height > max {
+                return Err(Error::LimitExceeded(format!("Image height {} is over the limit of {}", height, max)));
+            }
+        }
+        if let Some(max) = self.max_pixels {
+            let pixels = width.saturating_mul(height);
+            if pixels > max {
+                return Err(Error::LimitExceeded(format!("Image has {} pixels, which is over the limit of {}", pixels, max)));
+            }
+        }
+        if let Some(max) = self.max_memory {
+            let memory = Self::encoder_memory(width, height, bit_depth, sharp_yuv).saturating_add(other_memory);
+            if memory > max {
+                return Err(Error::LimitExceeded(format!("Image needs about {} bytes of memory, which is over the limit of {}", memory, max)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Approximate peak memory in bytes used by the encoder for an image of this size
+    pub fn encoder_memory(width: usize, height: usize, bit_depth: u8, sharp_yuv: bool) -> usize {
+        let sample_size = if bit_depth > 8 { 2 } else { 1 };
+        let bytes_per_pixel = ENCODER_BYTES_PER_PIXEL * sample_size + if sharp_yuv { SHARP_YUV_BYTES_PER_PIXEL } else { 0 };
+        width.saturating_mul(height).saturating_mul(bytes_per_pixel)
+    }
+}
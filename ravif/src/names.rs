@@ -0,0 +1,69 @@
+//! Names of setting values, shared by JSON settings, command-line options and other bindings
+
+/// Implements `name()`, `NAMES`, `FromStr` and `Display` for a fieldless enum, and with the
+/// `serde` feature, (de)serialization as the same names.
+macro_rules! named_values {
+    ($ty:ident, $label:literal { $($variant:ident => $name:literal),+ $(,)? }) => {
+        impl $ty {
+            /// All valid names, in the order of the variants
+            pub const NAMES: &'static [&'static str] = &[$($name),+];
+
+            /// Name of the value in settings, e.g. in JSON or command-line options
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Self::$variant => $name,)+
+                }
+            }
+        }
+
+        impl std::str::FromStr for $ty {
+            type Err = crate::Error;
+
+            fn from_str(s: &str) -> Result<Self, crate::Error> {
+                match s {
+                    $($name => Ok(Self::$variant),)+
+                    _ => Err(crate::Error::UnsupportedConfig(format!("{} must be one of {}, not '{}'", $label, Self::NAMES.join(", "), s))),
+                }
+            }
+        }
+
+        impl std::fmt::Display for $ty {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.name())
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl serde::Serialize for $ty {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.name())
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de> serde::Deserialize<'de> for $ty {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let name = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+                name.parse().map_err(|_| serde::de::Error::unknown_variant(&name, Self::NAMES))
+            }
+        }
+    };
+}
+
+#[test]
+fn names_round_trip() {
+    use crate::*;
+
+    fn check<T: std::str::FromStr + std::fmt::Display>(names: &[&str]) {
+        for &name in names {
+            assert_eq!(name, name.parse::<T>().ok().unwrap().to_string());
+        }
+        assert!("BT709".parse::<T>().is_err());
+    }
+    check::<ColorSpace>(ColorSpace::NAMES);
+    check::<MatrixCoefficients>(MatrixCoefficients::NAMES);
+    check::<ColorPrimaries>(ColorPrimaries::NAMES);
+    check::<TransferCharacteristics>(TransferCharacteristics::NAMES);
+    check::<ChromaSubsampling>(ChromaSubsampling::NAMES);
+    assert_eq!(Some(ChromaSubsampling::Yuv420), "420".parse().ok());
+}
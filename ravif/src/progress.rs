@@ -0,0 +1,56 @@
+use std::fmt;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+/// Flag that stops encoding early with [`Error::Cancelled`](crate::Error::Cancelled)
+///
+/// Clones share the same flag, so one can be kept to cancel from another thread
+/// while the other is in the [`Config`](crate::Config).
+///
+/// Cancelled encodes return within milliseconds. rav1e can't be interrupted in the middle of a frame,
+/// so it finishes the frame on a background thread, and the result is discarded.
+#[derive(Debug, Clone, Default)]
+pub struct CancelFlag(Arc<AtomicBool>);
+
+impl CancelFlag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make encodes using this flag stop as soon as possible
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Allow encoding again after [`cancel`](Self::cancel)
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Callback receiving encoding progress from `0.0` to `1.0`
+///
+/// It may be called from rayon's worker threads.
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(f32) + Send + Sync>);
+
+impl ProgressCallback {
+    pub fn new(callback: impl Fn(f32) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(callback))
+    }
+
+    pub(crate) fn report(&self, progress: f32) {
+        (self.0)(progress)
+    }
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
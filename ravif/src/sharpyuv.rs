@@ -0,0 +1,107 @@
+//! Iterative RGB to YCbCr conversion for subsampled chroma, similar to libwebp's "sharp YUV".
+//!
+//! Averaging chroma of neighboring pixels makes their colors wrong, and the error shows mostly as
+//! wrong brightness (e.g. dark fringes around red text on white). This repeatedly simulates how
+//! decoders upsample the chroma, and corrects luma and chroma by the difference from the source pixels.
+
+use crate::chroma::{box_average, ChromaSubsampling};
+use crate::cicp::MatrixCoefficients;
+
+/// More iterations bring little improvement
+const ITERATIONS: usize = 4;
+
+/// Full-resolution Y (0..=1), and subsampled Cb and Cr (-0.5..=0.5) for RGB pixels in 0..=1 range, using the `matrix`
+pub(crate) fn sharp_yuv(rgb: &[[f32; 3]], width: usize, height: usize, subsampling: ChromaSubsampling, matrix: MatrixCoefficients) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+    let ycbcr: Vec<_> = rgb.iter().map(|&px| matrix.rgb_to_ycbcr(px)).collect();
+    let mut y_plane: Vec<_> = ycbcr.iter().map(|px| px[0]).collect();
+    let mut cb_plane = downsample(&ycbcr.iter().map(|px| px[1]).collect::<Vec<_>>(), width, height, subsampling);
+    let mut cr_plane = downsample(&ycbcr.iter().map(|px| px[2]).collect::<Vec<_>>(), width, height, subsampling);
+    drop(ycbcr);
+
+    let mut cb_error = vec![0.; width * height];
+    let mut cr_error = vec![0.; width * height];
+    for _ in 0..ITERATIONS {
+        let cb_up = upsample(&cb_plane, width, height, subsampling);
+        let cr_up = upsample(&cr_plane, width, height, subsampling);
+        for (i, src) in rgb.iter().enumerate() {
+            let decoded = matrix.ycbcr_to_rgb([y_plane[i], cb_up[i], cr_up[i]]).map(|c| c.clamp(0., 1.));
+            let [y_err, cb_err, cr_err] = matrix.rgb_to_ycbcr([src[0] - decoded[0], src[1] - decoded[1], src[2] - decoded[2]]);
+            y_plane[i] = (y_plane[i] + y_err).clamp(0., 1.);
+            cb_error[i] = cb_err;
+            cr_error[i] = cr_err;
+        }
+        for (plane, error) in [(&mut cb_plane, &cb_error), (&mut cr_plane, &cr_error)] {
+            for (c, err) in plane.iter_mut().zip(downsample(error, width, height, subsampling)) {
+                *c = (*c + err).clamp(-0.5, 0.5);
+            }
+        }
+    }
+    (y_plane, cb_plane, cr_plane)
+}
+
+/// Box average, same as [`crate::chroma::downsample`] but without rounding
+fn downsample(plane: &[f32], width: usize, height: usize, subsampling: ChromaSubsampling) -> Vec<f32> {
+    box_average(plane, width, height, subsampling, |sum: f32, count| sum / count as f32)
+}
+
+/// Bilinear upsampling to full resolution, with chroma samples centered between luma samples,
+/// like the "fancy" upsampling done by decoders
+fn upsample(plane: &[f32], width: usize, height: usize, subsampling: ChromaSubsampling) -> Vec<f32> {
+    let (x_shift, y_shift) = subsampling.shifts();
+    let (chroma_width, chroma_height) = subsampling.chroma_size(width, height);
+    let wide: Vec<f32> = plane.chunks_exact(chroma_width)
+        .flat_map(|row| (0..width).map(move |x| interpolate(x, x_shift, chroma_width, |cx| row[cx])))
+        .collect();
+    (0..height)
+        .flat_map(|y| {
+            let wide = &wide;
+            (0..width).map(move |x| interpolate(y, y_shift, chroma_height, |cy| wide[cy * width + x]))
+        })
+        .collect()
+}
+
+/// Value at full-resolution `pos` of a line of `len` samples subsampled by `shift`
+#[inline]
+fn interpolate(pos: usize, shift: usize, len: usize, sample: impl Fn(usize) -> f32) -> f32 {
+    if shift == 0 {
+        return sample(pos);
+    }
+    let nearest = pos >> 1;
+    let neighbor = if pos & 1 == 0 { nearest.saturating_sub(1) } else { (nearest + 1).min(len - 1) };
+    0.75 * sample(nearest) + 0.25 * sample(neighbor)
+}
+
+#[test]
+fn red_text_on_white() {
+    let matrix = MatrixCoefficients::Bt709;
+    let (width, height) = (9, 7);
+    let rgb: Vec<[f32; 3]> = (0..width * height)
+        .map(|i| if (i % width) % 3 == 1 || (i / width) == 3 { [1., 0., 0.] } else { [1., 1., 1.] })
+        .collect();
+
+    let squared_error = |y: &[f32], cb: &[f32], cr: &[f32], subsampling| -> f32 {
+        let (cb, cr) = (upsample(cb, width, height, subsampling), upsample(cr, width, height, subsampling));
+        rgb.iter().enumerate().map(|(i, src)| {
+            let decoded = matrix.ycbcr_to_rgb([y[i], cb[i], cr[i]]).map(|c| c.clamp(0., 1.));
+            (0..3).map(|c| (src[c] - decoded[c]).powi(2)).sum::<f32>()
+        }).sum()
+    };
+
+    for subsampling in [ChromaSubsampling::Yuv420, ChromaSubsampling::Yuv422] {
+        let ycbcr: Vec<_> = rgb.iter().map(|&px| matrix.rgb_to_ycbcr(px)).collect();
+        let y: Vec<_> = ycbcr.iter().map(|px| px[0]).collect();
+        let cb = downsample(&ycbcr.iter().map(|px| px[1]).collect::<Vec<_>>(), width, height, subsampling);
+        let cr = downsample(&ycbcr.iter().map(|px| px[2]).collect::<Vec<_>>(), width, height, subsampling);
+        let naive = squared_error(&y, &cb, &cr, subsampling);
+
+        let (y, cb, cr) = sharp_yuv(&rgb, width, height, subsampling, matrix);
+        let sharp = squared_error(&y, &cb, &cr, subsampling);
+        assert!(sharp < naive * 0.75, "{:?}: {} vs {}", subsampling, sharp, naive);
+    }
+
+    let flat = vec![[0.25, 0.5, 0.75]; 4];
+    let (y, cb, cr) = sharp_yuv(&flat, 2, 2, ChromaSubsampling::Yuv420, matrix);
+    let expected = matrix.rgb_to_ycbcr(flat[0]);
+    assert!(y.iter().all(|&y| (y - expected[0]).abs() < 1e-5));
+    assert!((cb[0] - expected[1]).abs() < 1e-5 && (cr[0] - expected[2]).abs() < 1e-5);
+}
@@ -0,0 +1,383 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::error::Error;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::os::raw::c_int;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::fmt;
+use std::slice;
+use log::*;
+
+thread_local!{
+    static LAST_ERROR: RefCell<Option<FfiAvifError>> = RefCell::new(None);
+}
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Result of every fallible FFI function. `Ok` is always `0`.
+///
+/// Details of the error can be read on the same thread with `last_error_message()`.
+/// Every fallible FFI function also takes a last `error` argument, which may be null,
+/// for getting the details as an `FfiAvifErrorInfo` usable on any thread.
+///
+/// The values are part of the ABI, so new codes are only ever appended.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FfiAvifErrorCode {
+    Ok = 0,
+    /// A required pointer argument was null
+    NullInput = 1,
+    /// Input data is neither PNG nor JPEG
+    UnsupportedFormat = 2,
+    /// JPEG in CMYK color space, which can't be converted
+    CmykJpeg = 3,
+    /// Pixel buffer is smaller than its declared dimensions
+    TooFewPixels = 4,
+    /// rav1e failed to encode the image
+    EncoderFailure = 5,
+    /// An allocation failed
+    OutOfMemory = 6,
+    /// The encode was cancelled by the caller
+    Cancelled = 7,
+    /// The library panicked. This is a bug in the library.
+    Panic = 8,
+    /// An argument was out of range or inconsistent with other arguments
+    InvalidArgument = 9,
+    /// The PNG or JPEG file is corrupted
+    DecoderFailure = 10,
+    /// A caller-provided buffer can't fit the output
+    BufferTooSmall = 11,
+    /// The image is larger than the encoder's limits allow
+    LimitExceeded = 12,
+}
+
+// Error handling
+
+/// Calculate the number of bytes in the last error's error message **not**
+/// including any trailing `null` characters.
+#[no_mangle]
+pub extern "C" fn last_error_length() -> c_int {
+    catch_panic(-2, || {
+        LAST_ERROR.with(|prev| match *prev.borrow() {
+            Some(ref err) => err.to_string().len() as c_int + 1,
+            None => 0,
+        })
+    })
+}
+
+/// Write the most recent error message into a caller-provided buffer as a UTF-8
+/// string, returning the number of bytes written.
+///
+/// # Note
+///
+/// This writes a **UTF-8** string into the buffer. Windows users can use
+/// `last_error_message_utf16()` instead.
+///
+/// If there are no recent errors then this returns `0` (because we wrote 0
+/// bytes). `-1` is returned if there are any errors, for example when passed a
+/// null pointer or a buffer of insufficient size, and `-2` if the library panicked.
+#[no_mangle]
+pub unsafe extern "C" fn last_error_message(buffer: *mut c_char, length: c_int) -> c_int {
+    catch_panic(-2, || {
+        if buffer.is_null() {
+            warn!("Null pointer passed into last_error_message() as the buffer");
+            return -1;
+        }
+        if length <= 0 {
+            warn!("Non-positive length passed into last_error_message()");
+            return -1;
+        }
+
+        let last_error = match take_last_error() {
+            Some(err) => err,
+            None => return 0,
+        };
+
+        let error_message = last_error.to_string();
+
+        let buffer = slice::from_raw_parts_mut(buffer as *mut u8, length as usize);
+
+        if error_message.len() >= buffer.len() {
+            warn!("Buffer provided for writing the last error message is too small.");
+            warn!(
+                "Expected at least {} bytes but got {}",
+                error_message.len() + 1,
+                buffer.len()
+            );
+            return -1;
+        }
+
+        ptr::copy_nonoverlapping(
+            error_message.as_ptr(),
+            buffer.as_mut_ptr(),
+            error_message.len(),
+        );
+
+        // Add a trailing null so people using the string as a `char *` don't
+        // accidentally read into garbage.
+        buffer[error_message.len()] = 0;
+
+        error_message.len() as c_int
+    })
+}
+
+/// Calculate the number of UTF-16 code units in the last error's error message,
+/// including the trailing `null`.
+#[no_mangle]
+pub extern "C" fn last_error_length_utf16() -> c_int {
+    catch_panic(-2, || {
+        LAST_ERROR.with(|prev| match *prev.borrow() {
+            Some(ref err) => err.to_string().encode_utf16().count() as c_int + 1,
+            None => 0,
+        })
+    })
+}
+
+/// Write the most recent error message into a caller-provided buffer as a UTF-16
+/// string (in native byte order), returning the number of code units written.
+///
+/// `length` is the size of the buffer in code units (`u16`/`wchar_t` on Windows),
+/// not bytes. Return values are the same as of `last_error_message()`.
+#[no_mangle]
+pub unsafe extern "C" fn last_error_message_utf16(buffer: *mut u16, length: c_int) -> c_int {
+    catch_panic(-2, || {
+        if buffer.is_null() {
+            warn!("Null pointer passed into last_error_message_utf16() as the buffer");
+            return -1;
+        }
+        if length <= 0 {
+            warn!("Non-positive length passed into last_error_message_utf16()");
+            return -1;
+        }
+
+        let last_error = match take_last_error() {
+            Some(err) => err,
+            None => return 0,
+        };
+
+        let error_message: Vec<u16> = last_error.to_string().encode_utf16().collect();
+
+        let buffer = slice::from_raw_parts_mut(buffer, length as usize);
+
+        if error_message.len() >= buffer.len() {
+            warn!("Buffer provided for writing the last error message is too small.");
+            warn!(
+                "Expected at least {} code units but got {}",
+                error_message.len() + 1,
+                buffer.len()
+            );
+            return -1;
+        }
+
+        buffer[..error_message.len()].copy_from_slice(&error_message);
+        buffer[error_message.len()] = 0;
+
+        error_message.len() as c_int
+    })
+}
+
+/// Update the most recent error, clearing whatever may have been there before.
+pub fn update_last_error(err: FfiAvifError) {
+    error!("Setting LAST_ERROR: {}", err);
+
+    {
+        // Print a pseudo-backtrace for this error, following back each error's
+        // cause until we reach the root error.
+        let mut cause = err.source();
+        while let Some(parent_err) = cause {
+            warn!("Caused by: {}", parent_err);
+            cause = parent_err.source();
+        }
+    }
+
+    LAST_ERROR.with(|prev| {
+        *prev.borrow_mut() = Some(err);
+    });
+}
+
+/// Retrieve the most recent error, clearing it in the process.
+pub fn take_last_error() -> Option<FfiAvifError> {
+    LAST_ERROR.with(|prev| prev.borrow_mut().take())
+}
+
+/// Error details owned by the caller, returned through the `error` argument of fallible FFI functions.
+///
+/// Unlike `last_error_message()`, it can be read on any thread. Release it with `ffiavif_error_free()`.
+#[repr(C)]
+pub struct FfiAvifErrorInfo {
+    /// Same as returned by the function
+    pub code: FfiAvifErrorCode,
+    /// Nul-terminated UTF-8 message
+    pub message: *mut c_char,
+    /// Messages of the errors that caused this one, the most direct cause first
+    pub causes: *mut *mut c_char,
+    /// Number of messages in `causes`
+    pub cause_count: usize,
+}
+
+impl FfiAvifErrorInfo {
+    fn new(err: &FfiAvifError) -> *mut Self {
+        let mut causes = Vec::new();
+        let mut cause = err.source();
+        while let Some(parent_err) = cause {
+            causes.push(owned_c_string(&parent_err.to_string()));
+            cause = parent_err.source();
+        }
+        let causes = causes.into_boxed_slice();
+        Box::into_raw(Box::new(Self {
+            code: err.kind(),
+            message: owned_c_string(&err.to_string()),
+            cause_count: causes.len(),
+            causes: Box::into_raw(causes) as *mut *mut c_char,
+        }))
+    }
+}
+
+fn owned_c_string(s: &str) -> *mut c_char {
+    CString::new(s.replace('\0', "")).unwrap_or_default().into_raw()
+}
+
+/// Release an error returned through an `error` argument. Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_error_free(error: *mut FfiAvifErrorInfo) {
+    catch_panic((), || {
+        if error.is_null() {
+            return;
+        }
+        let error = Box::from_raw(error);
+        drop(CString::from_raw(error.message));
+        let causes = Box::from_raw(ptr::slice_from_raw_parts_mut(error.causes, error.cause_count));
+        for &cause in causes.iter() {
+            drop(CString::from_raw(cause));
+        }
+    })
+}
+
+/// Run the body of an FFI function, storing its error (or panic) for `last_error_message()`,
+/// and in `*error` unless it's null. `*error` is set to null on success.
+pub(crate) unsafe fn ffi_result(error: *mut *mut FfiAvifErrorInfo, f: impl FnOnce() -> Result<(), FfiAvifError>) -> FfiAvifErrorCode {
+    let res = panic::catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|payload| Err(panic_error(payload)));
+    let mut info = ptr::null_mut();
+    let code = match res {
+        Ok(()) => FfiAvifErrorCode::Ok,
+        Err(err) => {
+            if !error.is_null() {
+                info = FfiAvifErrorInfo::new(&err);
+            }
+            let code = err.kind();
+            update_last_error(err);
+            code
+        },
+    };
+    if !error.is_null() {
+        *error = info;
+    }
+    code
+}
+
+/// Panics must not unwind into the host application, because that's undefined behavior.
+/// A panic is stored as the last error, and `on_panic` is returned instead.
+pub(crate) fn catch_panic<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(res) => res,
+        Err(payload) => {
+            update_last_error(panic_error(payload));
+            on_panic
+        },
+    }
+}
+
+fn panic_error(payload: Box<dyn Any + Send>) -> FfiAvifError {
+    let msg = payload.downcast_ref::<&str>().copied()
+        .or_else(|| payload.downcast_ref::<String>().map(|s| s.as_str()))
+        .unwrap_or("unknown reason");
+    FfiAvifError::new(FfiAvifErrorCode::Panic, &format!("Internal error (panic): {}", msg))
+}
+
+#[derive(Debug)]
+pub struct FfiAvifError {
+    kind: FfiAvifErrorCode,
+    details: String,
+    source: Option<BoxError>,
+}
+
+impl FfiAvifError {
+    pub(crate) fn new(kind: FfiAvifErrorCode, msg: &str) -> FfiAvifError {
+        FfiAvifError{kind, details: msg.to_string(), source: None}
+    }
+
+    pub(crate) fn with_source(kind: FfiAvifErrorCode, msg: &str, source: impl Into<BoxError>) -> FfiAvifError {
+        FfiAvifError{kind, details: msg.to_string(), source: Some(source.into())}
+    }
+
+    /// Error code returned to the FFI caller
+    pub fn kind(&self) -> FfiAvifErrorCode {
+        self.kind
+    }
+}
+
+impl From<ravif::Error> for FfiAvifError {
+    fn from(err: ravif::Error) -> Self {
+        match err {
+            ravif::Error::TooFewPixels => FfiAvifError::new(FfiAvifErrorCode::TooFewPixels, &err.to_string()),
+            ravif::Error::Cancelled => FfiAvifError::new(FfiAvifErrorCode::Cancelled, &err.to_string()),
+            ravif::Error::LimitExceeded(msg) => FfiAvifError::new(FfiAvifErrorCode::LimitExceeded, &msg),
+            ravif::Error::UnsupportedConfig(msg) => FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, &msg),
+            ravif::Error::EncodingError(e) => FfiAvifError::with_source(FfiAvifErrorCode::EncoderFailure, &format!("Encoding error: {}", e), e),
+            err => FfiAvifError::new(FfiAvifErrorCode::EncoderFailure, &err.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for FfiAvifError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,"{}",self.details)
+    }
+}
+
+impl std::error::Error for FfiAvifError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|e| &**e as _)
+    }
+}
+
+#[test]
+fn panics_become_errors() {
+    assert_eq!(FfiAvifErrorCode::Panic, unsafe { ffi_result(ptr::null_mut(), || panic!("bad size {}", 3)) });
+    let err = take_last_error().unwrap();
+    assert_eq!(FfiAvifErrorCode::Panic, err.kind());
+    assert!(err.to_string().contains("bad size 3"));
+}
+
+#[test]
+fn utf16_messages() {
+    update_last_error(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Zły plik 🖼"));
+    assert_eq!(12, last_error_length_utf16());
+
+    let mut buffer = [0xFFFFu16; 16];
+    assert_eq!(11, unsafe { last_error_message_utf16(buffer.as_mut_ptr(), buffer.len() as c_int) });
+    let bytes: Vec<u8> = buffer[..12].iter().flat_map(|c| c.to_ne_bytes()).collect();
+    if cfg!(target_endian = "little") {
+        assert_eq!(&bytes[..], b"Z\0\x42\x01y\0 \0p\0l\0i\0k\0 \0\x3d\xd8\xbc\xdd\0\0");
+    }
+
+    // The message is taken by the first read
+    assert_eq!(0, last_error_length_utf16());
+    assert_eq!(0, unsafe { last_error_message_utf16(buffer.as_mut_ptr(), buffer.len() as c_int) });
+}
+
+#[test]
+fn negative_buffer_length() {
+    update_last_error(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "message"));
+    let mut buffer = [0xFFu8; 4];
+    assert_eq!(-1, unsafe { last_error_message(buffer.as_mut_ptr() as *mut c_char, -1) });
+    let mut buffer16 = [0xFFFFu16; 4];
+    assert_eq!(-1, unsafe { last_error_message_utf16(buffer16.as_mut_ptr(), 0) });
+    assert_eq!([0xFF; 4], buffer);
+    assert_eq!([0xFFFF; 4], buffer16);
+    // The error is still there for a call with a valid buffer
+    assert!(take_last_error().is_some());
+}
@@ -0,0 +1,91 @@
+use crate::*;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+/// Cancel flags of asynchronous jobs that haven't finished yet
+static JOBS: Mutex<BTreeMap<u64, CancelFlag>> = Mutex::new(BTreeMap::new());
+
+/// Job ids start at 1, so that 0 is never a valid id
+static NEXT_JOB_ID: AtomicU64 = AtomicU64::new(1);
+
+fn jobs() -> MutexGuard<'static, BTreeMap<u64, CancelFlag>> {
+    // The map is never left half-modified, so a poisoned lock is still usable
+    JOBS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Called once when an asynchronous encode has finished.
+///
+/// On success `code` is `FfiAvifErrorCode::Ok` and `output` is a buffer owned by the callback,
+/// which must be released with `ffiavif_buffer_free()`. Otherwise `output` is null, and the
+/// error message can be read with `last_error_message()` from within the callback.
+///
+/// The callback is called from the library's worker threads.
+pub type FfiAvifCompletionCallback = extern "C" fn(job_id: u64, code: FfiAvifErrorCode, output: *mut FfiAvifBuffer, user_data: *mut c_void);
+
+/// Decode a PNG or JPEG file and encode it as AVIF in the background, without blocking the caller.
+///
+/// The input data is copied, so it can be freed as soon as this function returns. The encoder's
+/// settings are copied too, and later changes to the encoder don't affect queued jobs.
+/// `ffiavif_encoder_cancel()` doesn't affect them either; use `ffiavif_cancel_job()` instead.
+///
+/// Jobs run on the library's worker pool. When a job finishes, `callback` is called exactly once
+/// with the result and `user_data`. If `job_id` isn't null, it's set to the id of the new job
+/// before the job starts, so the callback can't run before the id is known.
+///
+/// Returns `FfiAvifErrorCode::Ok` if the job has been queued. In that case errors of the
+/// encode itself are reported only to the callback.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encode_async(encoder: *const FfiAvifEncoder, data: *const c_char, data_size: usize, callback: Option<FfiAvifCompletionCallback>, user_data: *mut c_void, job_id: *mut u64, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    ffi_result(error, || {
+        let encoder = encoder_ref(encoder)?;
+        if data.is_null() {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::NullInput, "No input data pointer provided"));
+        }
+        let callback = callback.ok_or_else(|| FfiAvifError::new(FfiAvifErrorCode::NullInput, "No completion callback provided"))?;
+
+        let data = slice::from_raw_parts(data as *const u8, data_size).to_vec();
+
+        let cancel = CancelFlag::new();
+        let config = Config {
+            cancel: Some(cancel.clone()),
+            ..encoder.config.clone()
+        };
+
+        let id = NEXT_JOB_ID.fetch_add(1, Ordering::Relaxed);
+        jobs().insert(id, cancel);
+        // The callback may run before `rayon::spawn` returns, so the caller must know the id already
+        if !job_id.is_null() {
+            *job_id = id;
+        }
+
+        let user_data = UserData(user_data);
+        rayon::spawn(move || {
+            let mut output = ptr::null_mut();
+            let code = ffi_result(ptr::null_mut(), || {
+                output = encode_file(&data, &config)?;
+                Ok(())
+            });
+            jobs().remove(&id);
+            callback(id, code, output, user_data.0);
+        });
+        Ok(())
+    })
+}
+
+/// Make an asynchronous job stop as soon as possible (see `ffiavif_encoder_cancel()` for details).
+/// Its callback will still be called, usually with `FfiAvifErrorCode::Cancelled`.
+///
+/// Returns `FfiAvifErrorCode::Ok` on success, or `FfiAvifErrorCode::InvalidArgument` if there's
+/// no such job (it may have finished already).
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_cancel_job(job_id: u64, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    ffi_result(error, || {
+        let jobs = jobs();
+        let cancel = jobs.get(&job_id)
+            .ok_or_else(|| FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, &format!("There's no unfinished job {}", job_id)))?;
+        cancel.cancel();
+        Ok(())
+    })
+}
@@ -1,220 +1,1025 @@
 use imgref::ImgVec;
 use rayon::prelude::*;
-use std::cell::RefCell;
-use std::error::Error;
-//use std::error::Error;
+use std::borrow::Cow;
+use std::ffi::CStr;
+use std::ffi::CString;
 use std::os::raw::c_char;
 use std::os::raw::c_int;
+use std::os::raw::c_void;
 use std::ptr;
-use std::fmt;
 use std::slice;
-use log::*;
-//use clap::Format::Error;
 
-thread_local!{
-    static LAST_ERROR: RefCell<Option<Box<dyn Error>>> = RefCell::new(None);
-}
-
-type BoxError = Box<dyn Error + Send + Sync>;
+mod error;
+pub use error::*;
+mod jobs;
+pub use jobs::*;
+mod logging;
+pub use logging::*;
+mod probe;
+pub use probe::*;
+mod version;
+pub use version::*;
 
 use ravif::*;
 
+/// Encoded AVIF file owned by the library.
+///
+/// Release it with `ffiavif_buffer_free()`. Don't free `data` directly.
+#[repr(C)]
+pub struct FfiAvifBuffer {
+    /// Start of the AVIF file
+    pub data: *mut u8,
+    /// Size of the AVIF file in bytes
+    pub len: usize,
+    /// Size of the allocation. Only needed to release it.
+    pub capacity: usize,
+    /// What the file is made of and how long it took to encode
+    pub stats: FfiAvifEncodeStats,
+}
+
+/// Statistics about an encoded AVIF file
 #[repr(C)]
-struct Buffer {
-    data: *mut u8,
-    len: usize,
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FfiAvifEncodeStats {
+    /// Size of the AV1 payload of the color channels
+    pub color_bytes: usize,
+    /// Size of the AV1 payload of the alpha channel
+    pub alpha_bytes: usize,
+    /// Size of the AVIF/HEIF container around the payloads
+    pub container_bytes: usize,
+    /// False if the image was opaque and the alpha channel has been left out
+    pub has_alpha: bool,
+    /// Time spent encoding the color channels, in milliseconds
+    pub color_encode_ms: f64,
+    /// Time spent encoding the alpha channel, in milliseconds
+    pub alpha_encode_ms: f64,
 }
 
-pub extern "C" fn enc_rgba(data: *const c_char, dataSize: usize, config: &Config) -> *mut Buffer {
-    if data.is_null() {
-        let err = FfiAvifError::new("No input data pointer provided");
-        update_last_error(err);
-        return ptr::null_mut();
+impl FfiAvifEncodeStats {
+    fn new(img: &EncodedImage) -> Self {
+        Self {
+            color_bytes: img.color_byte_size,
+            alpha_bytes: img.alpha_byte_size,
+            container_bytes: img.avif_file.len() - img.color_byte_size - img.alpha_byte_size,
+            has_alpha: img.alpha_byte_size > 0,
+            color_encode_ms: img.color_encode_time.as_secs_f64() * 1000.,
+            alpha_encode_ms: img.alpha_encode_time.as_secs_f64() * 1000.,
+        }
     }
+}
 
-    let mut buffer: &[u8] = unsafe { std::slice::from_raw_parts(data as *const u8, dataSize) };
+impl FfiAvifBuffer {
+    fn new(img: EncodedImage) -> *mut Self {
+        let stats = FfiAvifEncodeStats::new(&img);
+        let mut data = std::mem::ManuallyDrop::new(img.avif_file);
+        Box::into_raw(Box::new(Self {
+            data: data.as_mut_ptr(),
+            len: data.len(),
+            capacity: data.capacity(),
+            stats,
+        }))
+    }
+}
 
-    let mut img = match load_rgba(&buffer, false) {
-        Ok(i) => i,
-        Err(_) => return ptr::null_mut(),
-        Err(e) => {
-            update_last_error(e.unwrap());
-            return ptr::null_mut();
+/// Release a buffer returned by one of the encode functions. Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_buffer_free(buffer: *mut FfiAvifBuffer) {
+    catch_panic((), || {
+        if !buffer.is_null() {
+            let buffer = Box::from_raw(buffer);
+            drop(Vec::from_raw_parts(buffer.data, buffer.len, buffer.capacity));
         }
-    };
+    })
+}
 
-    let (out_data, _, _) = match encode_rgba(img.as_ref(), config) {
-        Ok(d) => d,
-        Err(_) => return ptr::null_mut(),
-        Err(e) => {
-            update_last_error(e.into());
-            return ptr::null_mut();
+/// Copy an encoded file into memory owned by the caller, and release the buffer.
+///
+/// This allows hosts to keep the file in their own allocator: read the required size from
+/// `buffer->len`, allocate at least that many bytes, and pass them here as `dst`.
+///
+/// Returns `FfiAvifErrorCode::Ok` if the file has been copied. In that case `buffer` has been
+/// released and must not be used any more. If `dst_len` is too small, returns
+/// `FfiAvifErrorCode::BufferTooSmall` and leaves `buffer` untouched.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_buffer_take(buffer: *mut FfiAvifBuffer, dst: *mut u8, dst_len: usize, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    ffi_result(error, || {
+        let buf = buffer.as_ref().ok_or_else(|| FfiAvifError::new(FfiAvifErrorCode::NullInput, "No buffer pointer provided"))?;
+        if dst.is_null() {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::NullInput, "No destination pointer provided"));
         }
-    };
+        if dst_len < buf.len {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::BufferTooSmall, &format!("Destination has {} bytes, but {} are needed", dst_len, buf.len)));
+        }
+        ptr::copy_nonoverlapping(buf.data, dst, buf.len);
+        ffiavif_buffer_free(buffer);
+        Ok(())
+    })
+}
 
-    //let mut odata = out_data.align_to_mut();
+/// Opaque encoder handle that keeps encoding settings on the Rust side.
+///
+/// Create it with `ffiavif_encoder_new()`, adjust it with the `ffiavif_encoder_set_*()` functions
+/// and release it with `ffiavif_encoder_free()`.
+pub struct FfiAvifEncoder {
+    config: Config,
+    cancel: CancelFlag,
+}
 
-    let b = Buffer { data: out_data.as_ptr() as *mut u8, len: out_data.len()};
+/// Create a new encoder with default settings (quality 80, alpha quality 90, speed 4, YCbCr,
+/// one thread per core).
+///
+/// Returns null if the library panicked.
+#[no_mangle]
+pub extern "C" fn ffiavif_encoder_new() -> *mut FfiAvifEncoder {
+    catch_panic(ptr::null_mut(), || {
+        let cancel = CancelFlag::new();
+        Box::into_raw(Box::new(FfiAvifEncoder {
+            config: Config {
+                cancel: Some(cancel.clone()),
+                ..Config::default()
+            },
+            cancel,
+        }))
+    })
+}
 
-    &mut b
+/// Release an encoder created with `ffiavif_encoder_new()`. Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_free(encoder: *mut FfiAvifEncoder) {
+    catch_panic((), || {
+        if !encoder.is_null() {
+            drop(Box::from_raw(encoder));
+        }
+    })
 }
 
-extern "C" fn free_buf(buf: Buffer) {
-    let s = unsafe { std::slice::from_raw_parts_mut(buf.data, buf.len) };
-    let s = s.as_mut_ptr();
-    unsafe {
-        Box::from_raw(s);
-    }
+/// Set color quality from 1 (worst) to 100 (best).
+///
+/// Returns `FfiAvifErrorCode::Ok` on success.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_set_quality(encoder: *mut FfiAvifEncoder, quality: f32, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    with_encoder(encoder, error, |enc| {
+        if !(1. ..=100.).contains(&quality) {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Quality must be between 1 and 100"));
+        }
+        enc.config.quality = quality;
+        Ok(())
+    })
 }
 
-#[cfg(not(feature = "cocoa_image"))]
-fn load_rgba(mut data: &[u8], premultiplied_alpha: bool) -> Result<ImgVec<RGBA8>, Box<dyn std::error::Error + Send + Sync>> {
-    use rgb::FromSlice;
+/// Set alpha channel quality from 1 (worst) to 100 (best).
+///
+/// Returns `FfiAvifErrorCode::Ok` on success.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_set_alpha_quality(encoder: *mut FfiAvifEncoder, quality: f32, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    with_encoder(encoder, error, |enc| {
+        if !(1. ..=100.).contains(&quality) {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Alpha quality must be between 1 and 100"));
+        }
+        enc.config.alpha_quality = quality;
+        Ok(())
+    })
+}
 
-    let mut img = if data.get(0..4) == Some(&[0x89,b'P',b'N',b'G']) {
-        let img = lodepng::decode32(data)?;
-        ImgVec::new(img.buffer, img.width, img.height)
-    } else {
-        let mut jecoder = jpeg_decoder::Decoder::new(&mut data);
-        let pixels = jecoder.decode()?;
-        let info = jecoder.info().ok_or("Error reading JPEG info")?;
-        use jpeg_decoder::PixelFormat::*;
-        let buf: Vec<_> = match info.pixel_format {
-            L8 => {
-                pixels.iter().copied().map(|g| RGBA8::new(g,g,g,255)).collect()
-            },
-            RGB24 => {
-                let rgb = pixels.as_rgb();
-                rgb.iter().map(|p| p.alpha(255)).collect()
-            },
-            CMYK32 => return Err("CMYK JPEG is not supported. Please convert to PNG first".into()),
+/// Set encoding speed from 0 (best) to 10 (fast but ugly).
+///
+/// Returns `FfiAvifErrorCode::Ok` on success.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_set_speed(encoder: *mut FfiAvifEncoder, speed: u8, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    with_encoder(encoder, error, |enc| {
+        if speed > 10 {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Speed must be between 0 and 10"));
+        }
+        enc.config.speed = speed;
+        Ok(())
+    })
+}
+
+/// Set internal AVIF color space: `0` for YCbCr, `1` for RGB (larger files).
+///
+/// Returns `FfiAvifErrorCode::Ok` on success.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_set_color_space(encoder: *mut FfiAvifEncoder, color_space: c_int, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    with_encoder(encoder, error, |enc| {
+        enc.config.color_space = match color_space {
+            0 => ColorSpace::YCbCr,
+            1 => ColorSpace::RGB,
+            _ => return Err(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Color space must be 0 (YCbCr) or 1 (RGB)")),
         };
-        ImgVec::new(buf, info.width.into(), info.height.into())
-    };
-    if premultiplied_alpha {
-        img.pixels_mut().for_each(|px| {
-            px.r = (px.r as u16 * px.a as u16 / 255) as u8;
-            px.g = (px.g as u16 * px.a as u16 / 255) as u8;
-            px.b = (px.b as u16 * px.a as u16 / 255) as u8;
+        Ok(())
+    })
+}
+
+/// Set how RGB is converted to YCbCr, as an ITU-T H.273 code point:
+/// `1` for BT.709 (the default), `6` for BT.601, `9` for BT.2020 non-constant luminance, or `8` for YCgCo.
+///
+/// It's ignored with the RGB color space. YUV planes given directly must have been made with this matrix.
+///
+/// Returns `FfiAvifErrorCode::Ok` on success.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_set_matrix_coefficients(encoder: *mut FfiAvifEncoder, matrix_coefficients: c_int, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    with_encoder(encoder, error, |enc| {
+        enc.config.matrix_coefficients = match matrix_coefficients {
+            1 => MatrixCoefficients::Bt709,
+            6 => MatrixCoefficients::Bt601,
+            9 => MatrixCoefficients::Bt2020Ncl,
+            8 => MatrixCoefficients::YCgCo,
+            _ => return Err(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Matrix coefficients must be 1 (BT.709), 6 (BT.601), 9 (BT.2020 NCL) or 8 (YCgCo)")),
+        };
+        Ok(())
+    })
+}
+
+/// Set color primaries the pixels are in, as an ITU-T H.273 code point:
+/// `1` for BT.709/sRGB (the default), `6` for BT.601, `9` for BT.2020, or `12` for Display P3.
+///
+/// Pixels are not converted, this only labels them.
+///
+/// Returns `FfiAvifErrorCode::Ok` on success.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_set_color_primaries(encoder: *mut FfiAvifEncoder, color_primaries: c_int, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    with_encoder(encoder, error, |enc| {
+        enc.config.color_primaries = match color_primaries {
+            1 => ColorPrimaries::Bt709,
+            6 => ColorPrimaries::Bt601,
+            9 => ColorPrimaries::Bt2020,
+            12 => ColorPrimaries::DisplayP3,
+            _ => return Err(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Color primaries must be 1 (BT.709), 6 (BT.601), 9 (BT.2020) or 12 (Display P3)")),
+        };
+        Ok(())
+    })
+}
+
+/// Set transfer function of the pixels, as an ITU-T H.273 code point:
+/// `13` for sRGB (the default), `1` for BT.709, `8` for linear, `16` for PQ, or `18` for HLG.
+///
+/// Pixels are not converted, this only labels them.
+///
+/// Returns `FfiAvifErrorCode::Ok` on success.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_set_transfer_characteristics(encoder: *mut FfiAvifEncoder, transfer_characteristics: c_int, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    with_encoder(encoder, error, |enc| {
+        enc.config.transfer_characteristics = match transfer_characteristics {
+            13 => TransferCharacteristics::Srgb,
+            1 => TransferCharacteristics::Bt709,
+            8 => TransferCharacteristics::Linear,
+            16 => TransferCharacteristics::Pq,
+            18 => TransferCharacteristics::Hlg,
+            _ => return Err(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Transfer characteristics must be 13 (sRGB), 1 (BT.709), 8 (linear), 16 (PQ) or 18 (HLG)")),
+        };
+        Ok(())
+    })
+}
+
+/// Set bits per channel of the AVIF file: 8, 10 or 12. Higher depths prevent banding in smooth gradients.
+///
+/// Returns `FfiAvifErrorCode::Ok` on success.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_set_bit_depth(encoder: *mut FfiAvifEncoder, bit_depth: c_int, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    with_encoder(encoder, error, |enc| {
+        enc.config.bit_depth = match bit_depth {
+            8 | 10 | 12 => bit_depth as u8,
+            _ => return Err(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Bit depth must be 8, 10 or 12")),
+        };
+        Ok(())
+    })
+}
+
+/// Set resolution of color relative to brightness: `444` for full resolution (the default),
+/// `422` for half horizontal resolution, or `420` for half horizontal and vertical resolution.
+///
+/// Subsampled color makes smaller files, but can't be used with the RGB color space.
+///
+/// Returns `FfiAvifErrorCode::Ok` on success.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_set_chroma_subsampling(encoder: *mut FfiAvifEncoder, subsampling: c_int, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    with_encoder(encoder, error, |enc| {
+        enc.config.chroma_subsampling = match subsampling {
+            444 => ChromaSubsampling::Yuv444,
+            422 => ChromaSubsampling::Yuv422,
+            420 => ChromaSubsampling::Yuv420,
+            _ => return Err(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Chroma subsampling must be 444, 422 or 420")),
+        };
+        Ok(())
+    })
+}
+
+/// Enable slower RGB to YCbCr conversion that keeps edges between colors sharp when chroma is subsampled.
+///
+/// It has no effect without chroma subsampling, nor on YUV planes given directly.
+///
+/// Returns `FfiAvifErrorCode::Ok` on success.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_set_sharp_yuv(encoder: *mut FfiAvifEncoder, sharp_yuv: bool, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    with_encoder(encoder, error, |enc| {
+        enc.config.sharp_yuv = sharp_yuv;
+        Ok(())
+    })
+}
+
+/// Set maximum number of threads to use (0 = one thread per host core).
+///
+/// Returns `FfiAvifErrorCode::Ok` on success.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_set_threads(encoder: *mut FfiAvifEncoder, threads: usize, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    with_encoder(encoder, error, |enc| {
+        enc.config.threads = threads;
+        Ok(())
+    })
+}
+
+/// Reject images larger than the given limits before decoding or encoding them.
+///
+/// `max_memory` is an approximate budget for peak memory use in bytes. `0` means no limit.
+/// Images over a limit fail with `FfiAvifErrorCode::LimitExceeded`.
+///
+/// Returns `FfiAvifErrorCode::Ok` on success.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_set_limits(encoder: *mut FfiAvifEncoder, max_width: usize, max_height: usize, max_pixels: usize, max_memory: usize, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    let limit = |max| if max > 0 { Some(max) } else { None };
+    with_encoder(encoder, error, |enc| {
+        enc.config.limits = Limits {
+            max_width: limit(max_width),
+            max_height: limit(max_height),
+            max_pixels: limit(max_pixels),
+            max_memory: limit(max_memory),
+        };
+        Ok(())
+    })
+}
+
+/// Called with encoding progress from `0.0` to `1.0`
+pub type FfiAvifProgressCallback = extern "C" fn(progress: f32, user_data: *mut c_void);
+
+/// Set a callback receiving encoding progress, or remove it by passing null.
+///
+/// `user_data` is passed to the callback unchanged. The callback may be called from
+/// the library's worker threads.
+///
+/// Returns `FfiAvifErrorCode::Ok` on success.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_set_progress_callback(encoder: *mut FfiAvifEncoder, callback: Option<FfiAvifProgressCallback>, user_data: *mut c_void, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    with_encoder(encoder, error, |enc| {
+        enc.config.progress = callback.map(|callback| {
+            let user_data = UserData(user_data);
+            ProgressCallback::new(move |progress| callback(progress, user_data.0))
         });
-    }
-    Ok(img)
+        Ok(())
+    })
 }
 
-#[cfg(feature = "cocoa_image")]
-fn load_rgba(data: &[u8], premultiplied_alpha: bool) -> Result<ImgVec<RGBA8>, BoxError> {
-    if premultiplied_alpha {
-        Ok(cocoa_image::decode_image_as_rgba_premultiplied(data)?)
-    } else {
-        Ok(cocoa_image::decode_image_as_rgba(data)?)
-    }
+/// Make encodes using this encoder stop as soon as possible with `FfiAvifErrorCode::Cancelled`.
+///
+/// It's safe to call from any thread while the encoder is in use. The encoder stays
+/// cancelled until `ffiavif_encoder_reset_cancel()` is called.
+///
+/// Encodes in progress return within milliseconds. The AV1 encoder can't be interrupted in the middle
+/// of a frame, so it finishes the frame in the background and then discards it, and until then
+/// it keeps using CPU time and memory.
+///
+/// Returns `FfiAvifErrorCode::Ok` on success.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_cancel(encoder: *const FfiAvifEncoder, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    ffi_result(error, || {
+        encoder_ref(encoder)?.cancel.cancel();
+        Ok(())
+    })
+}
+
+/// Allow encoding again after `ffiavif_encoder_cancel()`.
+///
+/// Returns `FfiAvifErrorCode::Ok` on success.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_reset_cancel(encoder: *const FfiAvifEncoder, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    ffi_result(error, || {
+        encoder_ref(encoder)?.cancel.reset();
+        Ok(())
+    })
 }
 
-// Error handling
+/// Host-provided pointer given back to callbacks
+#[derive(Copy, Clone)]
+struct UserData(*mut c_void);
 
-/// Calculate the number of bytes in the last error's error message **not**
-/// including any trailing `null` characters.
+// It's up to the host to make the user data usable from other threads
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+unsafe fn encoder_ref<'a>(encoder: *const FfiAvifEncoder) -> Result<&'a FfiAvifEncoder, FfiAvifError> {
+    encoder.as_ref().ok_or_else(|| FfiAvifError::new(FfiAvifErrorCode::NullInput, "No encoder pointer provided"))
+}
+
+unsafe fn with_encoder(encoder: *mut FfiAvifEncoder, error: *mut *mut FfiAvifErrorInfo, f: impl FnOnce(&mut FfiAvifEncoder) -> Result<(), FfiAvifError>) -> FfiAvifErrorCode {
+    ffi_result(error, || {
+        let enc = encoder.as_mut().ok_or_else(|| FfiAvifError::new(FfiAvifErrorCode::NullInput, "No encoder pointer provided"))?;
+        f(enc)
+    })
+}
+
+/// Decode a PNG or JPEG file and encode it as AVIF.
+///
+/// On success `*output` is set to a buffer that must be released with `ffiavif_buffer_free()`.
+///
+/// Returns `FfiAvifErrorCode::Ok` on success. Details of the error can be read with `last_error_message()`.
 #[no_mangle]
-pub extern "C" fn last_error_length() -> c_int {
-    LAST_ERROR.with(|prev| match *prev.borrow() {
-        Some(ref err) => err.to_string().len() as c_int + 1,
-        None => 0,
+pub unsafe extern "C" fn ffiavif_encoder_encode(encoder: *const FfiAvifEncoder, data: *const c_char, data_size: usize, output: *mut *mut FfiAvifBuffer, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    ffi_result(error, || {
+        let encoder = encoder_ref(encoder)?;
+        if data.is_null() {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::NullInput, "No input data pointer provided"));
+        }
+        if output.is_null() {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::NullInput, "No output pointer provided"));
+        }
+
+        let buffer: &[u8] = std::slice::from_raw_parts(data as *const u8, data_size);
+
+        *output = encode_file(buffer, &encoder.config)?;
+        Ok(())
     })
 }
 
-/// Write the most recent error message into a caller-provided buffer as a UTF-8
-/// string, returning the number of bytes written.
+/// Encode raw RGBA pixels (non-premultiplied, alpha last, 4 bytes per pixel) without decoding
+/// them first.
 ///
-/// # Note
+/// `stride` is the number of bytes between the starts of consecutive rows. It must be a
+/// multiple of 4 and at least `width * 4`.
 ///
-/// This writes a **UTF-8** string into the buffer. Windows users may need to
-/// convert it to a UTF-16 "unicode" afterwards.
+/// On success `*output` is set to a buffer that must be released with `ffiavif_buffer_free()`.
 ///
-/// If there are no recent errors then this returns `0` (because we wrote 0
-/// bytes). `-1` is returned if there are any errors, for example when passed a
-/// null pointer or a buffer of insufficient size.
+/// Returns `FfiAvifErrorCode::Ok` on success. Details of the error can be read with `last_error_message()`.
 #[no_mangle]
-pub unsafe extern "C" fn last_error_message(buffer: *mut c_char, length: c_int) -> c_int {
-    if buffer.is_null() {
-        warn!("Null pointer passed into last_error_message() as the buffer");
-        return -1;
+pub unsafe extern "C" fn ffiavif_encoder_encode_rgba(encoder: *const FfiAvifEncoder, pixels: *const u8, width: usize, height: usize, stride: usize, output: *mut *mut FfiAvifBuffer, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    ffi_result(error, || {
+        let encoder = encoder_ref(encoder)?;
+        if pixels.is_null() {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::NullInput, "No input pixels pointer provided"));
+        }
+        if output.is_null() {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::NullInput, "No output pointer provided"));
+        }
+
+        if width == 0 || height == 0 {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Image width and height must be non-zero"));
+        }
+
+        if stride % 4 != 0 || stride / 4 < width {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Stride must be a multiple of 4 and at least width * 4 bytes"));
+        }
+
+        let data_size = stride.checked_mul(height - 1).and_then(|s| s.checked_add(width * 4))
+            .ok_or_else(|| FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Image is too large"))?;
+
+        use rgb::FromSlice;
+        let pixels = slice::from_raw_parts(pixels, data_size).as_rgba();
+
+        *output = encode_img(Img::new_stride(pixels, width, height, stride / 4), &encoder.config)?;
+        Ok(())
+    })
+}
+
+/// Settings of the deprecated `enc_rgba()` and `enc_rgba_pixels()` functions.
+///
+/// This is the layout of ravif's `Config` from before the encoder handle existed,
+/// and it must not change.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct LegacyConfig {
+    /// 0-100 scale
+    pub quality: f32,
+    /// 0-100 scale
+    pub alpha_quality: f32,
+    /// rav1e preset 1 (slow) 10 (fast but crappy)
+    pub speed: u8,
+    /// True if RGBA input has already been premultiplied. It inserts appropriate metadata.
+    pub premultiplied_alpha: bool,
+    /// Which pixel format to use in AVIF file. RGB tends to give larger files.
+    pub color_space: ColorSpace,
+    /// How many threads should be used (0 = match core count)
+    pub threads: usize,
+}
+
+impl From<&LegacyConfig> for Config {
+    fn from(legacy: &LegacyConfig) -> Self {
+        Self {
+            quality: legacy.quality,
+            alpha_quality: legacy.alpha_quality,
+            speed: legacy.speed,
+            premultiplied_alpha: legacy.premultiplied_alpha,
+            color_space: legacy.color_space,
+            threads: legacy.threads,
+            ..Config::default()
+        }
     }
+}
+
+/// Decode a PNG or JPEG file and encode it as AVIF with the given settings.
+///
+/// Returns null on failure. The error can be read with `last_error_message()`.
+///
+/// Deprecated: use `ffiavif_encoder_new()` and `ffiavif_encoder_encode()` instead.
+#[no_mangle]
+#[deprecated(note = "use ffiavif_encoder_encode()")]
+pub unsafe extern "C" fn enc_rgba(data: *const c_char, data_size: usize, config: &LegacyConfig) -> *mut FfiAvifBuffer {
+    encode_with_config(config, |encoder, output| ffiavif_encoder_encode(encoder, data, data_size, output, ptr::null_mut()))
+}
+
+/// Encode raw RGBA pixels (non-premultiplied, alpha last, 4 bytes per pixel) with the given settings.
+///
+/// `stride` is the number of bytes between the starts of consecutive rows. It must be a
+/// multiple of 4 and at least `width * 4`.
+///
+/// Returns null on failure. The error can be read with `last_error_message()`.
+///
+/// Deprecated: use `ffiavif_encoder_new()` and `ffiavif_encoder_encode_rgba()` instead.
+#[no_mangle]
+#[deprecated(note = "use ffiavif_encoder_encode_rgba()")]
+pub unsafe extern "C" fn enc_rgba_pixels(pixels: *const u8, width: usize, height: usize, stride: usize, config: &LegacyConfig) -> *mut FfiAvifBuffer {
+    encode_with_config(config, |encoder, output| ffiavif_encoder_encode_rgba(encoder, pixels, width, height, stride, output, ptr::null_mut()))
+}
 
-    let last_error = match take_last_error() {
-        Some(err) => err,
-        None => return 0,
+/// Runs an encoder function on a temporary encoder with the `config` settings
+fn encode_with_config(config: &LegacyConfig, f: impl FnOnce(&FfiAvifEncoder, *mut *mut FfiAvifBuffer) -> FfiAvifErrorCode) -> *mut FfiAvifBuffer {
+    let encoder = FfiAvifEncoder {
+        config: config.into(),
+        cancel: CancelFlag::new(),
     };
+    let mut output = ptr::null_mut();
+    match f(&encoder, &mut output) {
+        FfiAvifErrorCode::Ok => output,
+        _ => ptr::null_mut(),
+    }
+}
 
-    let error_message = last_error.to_string();
+/// Order of channels in a pixel, from the lowest address. All channels are 8-bit.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FfiAvifPixelFormat {
+    Rgba = 0,
+    Bgra = 1,
+    Argb = 2,
+    Abgr = 3,
+    Rgb = 4,
+    Bgr = 5,
+}
 
-    let buffer = slice::from_raw_parts_mut(buffer as *mut u8, length as usize);
+impl FfiAvifPixelFormat {
+    fn from_c(format: c_int) -> Option<Self> {
+        Some(match format {
+            0 => Self::Rgba,
+            1 => Self::Bgra,
+            2 => Self::Argb,
+            3 => Self::Abgr,
+            4 => Self::Rgb,
+            5 => Self::Bgr,
+            _ => return None,
+        })
+    }
 
-    if error_message.len() >= buffer.len() {
-        warn!("Buffer provided for writing the last error message is too small.");
-        warn!(
-            "Expected at least {} bytes but got {}",
-            error_message.len() + 1,
-            buffer.len()
-        );
-        return -1;
+    fn bytes_per_pixel(self) -> usize {
+        match self {
+            Self::Rgb | Self::Bgr => 3,
+            _ => 4,
+        }
     }
 
-    ptr::copy_nonoverlapping(
-        error_message.as_ptr(),
-        buffer.as_mut_ptr(),
-        error_message.len(),
-    );
+    fn to_rgba(self) -> fn(&[u8]) -> RGBA8 {
+        match self {
+            Self::Rgba => |p| RGBA8::new(p[0], p[1], p[2], p[3]),
+            Self::Bgra => |p| RGBA8::new(p[2], p[1], p[0], p[3]),
+            Self::Argb => |p| RGBA8::new(p[1], p[2], p[3], p[0]),
+            Self::Abgr => |p| RGBA8::new(p[3], p[2], p[1], p[0]),
+            Self::Rgb => |p| RGBA8::new(p[0], p[1], p[2], 255),
+            Self::Bgr => |p| RGBA8::new(p[2], p[1], p[0], 255),
+        }
+    }
+}
+
+/// Encode raw pixels in any of the `FfiAvifPixelFormat` layouts (non-premultiplied), converting
+/// them while they're read.
+///
+/// `pixels` points to the first byte of the top row. `stride` is the number of bytes from the
+/// start of one row to the start of the row below it. It's negative for bottom-up images
+/// (where the top row is last in memory), and its absolute value must be at least
+/// `width * bytes per pixel`.
+///
+/// `format` is one of the `FfiAvifPixelFormat` values.
+///
+/// On success `*output` is set to a buffer that must be released with `ffiavif_buffer_free()`.
+///
+/// Returns `FfiAvifErrorCode::Ok` on success. Details of the error can be read with `last_error_message()`.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_encode_pixels(encoder: *const FfiAvifEncoder, pixels: *const u8, width: usize, height: usize, stride: isize, format: c_int, output: *mut *mut FfiAvifBuffer, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    ffi_result(error, || {
+        let encoder = encoder_ref(encoder)?;
+        if pixels.is_null() {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::NullInput, "No input pixels pointer provided"));
+        }
+        if output.is_null() {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::NullInput, "No output pointer provided"));
+        }
+        let format = FfiAvifPixelFormat::from_c(format)
+            .ok_or_else(|| FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Unknown pixel format"))?;
+
+        if width == 0 || height == 0 {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Image width and height must be non-zero"));
+        }
+
+        let too_large = || FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Image is too large");
+        let row_bytes = width.checked_mul(format.bytes_per_pixel()).ok_or_else(too_large)?;
+        let abs_stride = stride.unsigned_abs();
+        if abs_stride < row_bytes {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Stride must be at least width * bytes per pixel"));
+        }
+        let gap = abs_stride.checked_mul(height - 1).ok_or_else(too_large)?;
+        let data_size = gap.checked_add(row_bytes).filter(|&s| s <= isize::MAX as usize).ok_or_else(too_large)?;
+
+        // The slice starts at the lowest address, which is the bottom row when the stride is negative
+        let start = if stride < 0 { pixels.sub(gap) } else { pixels };
+        let data = slice::from_raw_parts(start, data_size);
+        let rows = (0..height).map(|y| {
+            let offset = if stride < 0 { (height - 1 - y) * abs_stride } else { y * abs_stride };
+            &data[offset..offset + row_bytes]
+        });
 
-    // Add a trailing null so people using the string as a `char *` don't
-    // accidentally read into garbage.
-    buffer[error_message.len()] = 0;
+        let to_rgba = format.to_rgba();
+        let pixels = rows.flat_map(|row| row.chunks_exact(format.bytes_per_pixel()).map(to_rgba));
+        *output = FfiAvifBuffer::new(encode_rgba_pixels(width, height, pixels, &encoder.config)?);
+        Ok(())
+    })
+}
 
-    error_message.len() as c_int
+/// Encode planes of YUV pixels (and optionally alpha) without converting them from RGB.
+///
+/// All planes are 8-bit. If the encoder's bit depth is higher, the samples are scaled up to it.
+/// With the default color space the YUV planes must use the encoder's matrix coefficients (BT.709 unless set with
+/// `ffiavif_encoder_set_matrix_coefficients()`). If the encoder's color space is set to RGB, they are
+/// G, B and R planes instead. `alpha` may be null for opaque images.
+///
+/// U and V planes have the resolution set with `ffiavif_encoder_set_chroma_subsampling()`.
+/// When they're subsampled, their width and height are half of the image's, rounded up.
+///
+/// Each `*_stride` is the number of bytes between the starts of consecutive rows of that
+/// plane, and must be at least the width of the plane.
+///
+/// `pixel_range` is `0` for limited (studio, 16-235) range and `1` for full range.
+///
+/// On success `*output` is set to a buffer that must be released with `ffiavif_buffer_free()`.
+///
+/// Returns `FfiAvifErrorCode::Ok` on success. Details of the error can be read with `last_error_message()`.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_encode_yuv(encoder: *const FfiAvifEncoder,
+    y: *const u8, y_stride: usize, u: *const u8, u_stride: usize, v: *const u8, v_stride: usize, alpha: *const u8, alpha_stride: usize,
+    width: usize, height: usize, pixel_range: c_int, output: *mut *mut FfiAvifBuffer, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    ffi_result(error, || {
+        let encoder = encoder_ref(encoder)?;
+        let pixel_range = check_yuv_args(encoder, width, height, pixel_range, output)?;
+
+        let (chroma_width, chroma_height) = encoder.config.chroma_subsampling.chroma_size(width, height);
+        let y = packed_plane(y, y_stride, width, height, "Y")?;
+        let u = packed_plane(u, u_stride, chroma_width, chroma_height, "U")?;
+        let v = packed_plane(v, v_stride, chroma_width, chroma_height, "V")?;
+        let alpha = if alpha.is_null() { None } else { Some(packed_plane(alpha, alpha_stride, width, height, "alpha")?) };
+
+        let out = encode_raw_planes(width, height, &y, &u, &v, alpha.as_deref(), pixel_range, &encoder.config)?;
+        *output = FfiAvifBuffer::new(out);
+        Ok(())
+    })
 }
 
-/// Update the most recent error, clearing whatever may have been there before.
-pub fn update_last_error<E: std::error::Error + 'static>(err: E) {
-    error!("Setting LAST_ERROR: {}", err);
+/// Same as `ffiavif_encoder_encode_yuv()`, but the samples are 16-bit integers with the encoder's
+/// bit depth (set with `ffiavif_encoder_set_bit_depth()`). They're not scaled, so for 10-bit
+/// depth full range is 0-1023.
+///
+/// Strides are in bytes, and must be multiples of 2.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_encode_yuv16(encoder: *const FfiAvifEncoder,
+    y: *const u16, y_stride: usize, u: *const u16, u_stride: usize, v: *const u16, v_stride: usize, alpha: *const u16, alpha_stride: usize,
+    width: usize, height: usize, pixel_range: c_int, output: *mut *mut FfiAvifBuffer, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    ffi_result(error, || {
+        let encoder = encoder_ref(encoder)?;
+        let pixel_range = check_yuv_args(encoder, width, height, pixel_range, output)?;
+
+        let (chroma_width, chroma_height) = encoder.config.chroma_subsampling.chroma_size(width, height);
+        let y = packed_plane(y, y_stride, width, height, "Y")?;
+        let u = packed_plane(u, u_stride, chroma_width, chroma_height, "U")?;
+        let v = packed_plane(v, v_stride, chroma_width, chroma_height, "V")?;
+        let alpha = if alpha.is_null() { None } else { Some(packed_plane(alpha, alpha_stride, width, height, "alpha")?) };
 
-    {
-        // Print a pseudo-backtrace for this error, following back each error's
-        // cause until we reach the root error.
-        let mut cause = err.source();
-        while let Some(parent_err) = cause {
-            warn!("Caused by: {}", parent_err);
-            cause = parent_err.source();
-        }
+        let out = encode_raw_planes_u16(width, height, &y, &u, &v, alpha.as_deref(), pixel_range, &encoder.config)?;
+        *output = FfiAvifBuffer::new(out);
+        Ok(())
+    })
+}
+
+fn check_yuv_args(encoder: &FfiAvifEncoder, width: usize, height: usize, pixel_range: c_int, output: *mut *mut FfiAvifBuffer) -> Result<PixelRange, FfiAvifError> {
+    if output.is_null() {
+        return Err(FfiAvifError::new(FfiAvifErrorCode::NullInput, "No output pointer provided"));
     }
+    if width == 0 || height == 0 {
+        return Err(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Image width and height must be non-zero"));
+    }
+    let pixel_range = match pixel_range {
+        0 => PixelRange::Limited,
+        1 => PixelRange::Full,
+        _ => return Err(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Pixel range must be 0 (limited) or 1 (full)")),
+    };
+    encoder.config.limits.check(width, height, encoder.config.bit_depth, false, 0)?;
+    Ok(pixel_range)
+}
 
-    LAST_ERROR.with(|prev| {
-        *prev.borrow_mut() = Some(Box::new(err));
-    });
+/// Plane without padding between rows, as needed by `encode_raw_planes`. Copied only if the stride has padding.
+///
+/// `stride` is in bytes.
+unsafe fn packed_plane<'a, T: Copy>(plane: *const T, stride: usize, width: usize, height: usize, name: &str) -> Result<Cow<'a, [T]>, FfiAvifError> {
+    if plane.is_null() {
+        return Err(FfiAvifError::new(FfiAvifErrorCode::NullInput, &format!("No {} plane pointer provided", name)));
+    }
+    let sample_size = std::mem::size_of::<T>();
+    if stride % sample_size != 0 {
+        return Err(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, &format!("{} plane stride must be a multiple of {}", name, sample_size)));
+    }
+    let stride = stride / sample_size;
+    if stride < width {
+        return Err(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, &format!("{} plane stride must be at least width", name)));
+    }
+    let data_size = stride.checked_mul(height - 1).and_then(|s| s.checked_add(width))
+        .ok_or_else(|| FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Image is too large"))?;
+    let data = slice::from_raw_parts(plane, data_size);
+    Ok(if stride == width {
+        Cow::Borrowed(data)
+    } else {
+        Cow::Owned(Img::new_stride(data, width, height, stride).rows().flatten().copied().collect())
+    })
 }
 
-/// Retrieve the most recent error, clearing it in the process.
-pub fn take_last_error() -> Option<Box<dyn std::error::Error>> {
-    LAST_ERROR.with(|prev| prev.borrow_mut().take())
+/// One input file of `ffiavif_encoder_encode_batch()`
+#[repr(C)]
+pub struct FfiAvifInput {
+    /// PNG or JPEG file
+    pub data: *const c_char,
+    /// Size of the file in bytes
+    pub len: usize,
 }
 
-#[derive(Debug)]
-pub struct FfiAvifError {
-    details: String
+/// Result of encoding one file of a batch
+#[repr(C)]
+pub struct FfiAvifBatchResult {
+    /// `FfiAvifErrorCode::Ok` if this file has been encoded
+    pub code: FfiAvifErrorCode,
+    /// Encoded file, or null on error
+    pub output: *mut FfiAvifBuffer,
+    /// Nul-terminated error message, or null on success
+    pub message: *mut c_char,
 }
 
-impl FfiAvifError {
-    fn new(msg: &str) -> FfiAvifError {
-        FfiAvifError{details: msg.to_string()}
+/// Decode and encode many PNG or JPEG files at once, in parallel.
+///
+/// All files use the encoder's settings. Progress isn't reported for batches, but
+/// `ffiavif_encoder_cancel()` stops all files that aren't finished yet.
+///
+/// On success `*results` is set to an array of `count` results, in the same order as the
+/// inputs. Each file succeeds or fails on its own, so check the `code` of each result. The
+/// array, with all of its buffers and messages, must be released with
+/// `ffiavif_batch_results_free()`. To keep a buffer after that, set its `output` to null
+/// first, and release it later with `ffiavif_buffer_free()`.
+///
+/// Returns `FfiAvifErrorCode::Ok` if the batch has been processed, even if some files failed.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encoder_encode_batch(encoder: *const FfiAvifEncoder, inputs: *const FfiAvifInput, count: usize, results: *mut *mut FfiAvifBatchResult, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    ffi_result(error, || {
+        let encoder = encoder_ref(encoder)?;
+        if inputs.is_null() && count > 0 {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::NullInput, "No inputs pointer provided"));
+        }
+        if results.is_null() {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::NullInput, "No results pointer provided"));
+        }
+
+        let inputs: Vec<Option<&[u8]>> = if count > 0 { slice::from_raw_parts(inputs, count) } else { &[] }
+            .iter()
+            .map(|input| if input.data.is_null() { None } else { Some(slice::from_raw_parts(input.data as *const u8, input.len)) })
+            .collect();
+
+        let config = Config {
+            progress: None,
+            ..encoder.config.clone()
+        };
+
+        let encoded: Vec<_> = inputs.into_par_iter().map(|data| {
+            let mut out = None;
+            let code = ffi_result(ptr::null_mut(), || {
+                let data = data.ok_or_else(|| FfiAvifError::new(FfiAvifErrorCode::NullInput, "No input data pointer provided"))?;
+                let img = load_image_limited(data, &config)?;
+                out = Some(img.encode(&config)?);
+                Ok(())
+            });
+            let message = if code != FfiAvifErrorCode::Ok { take_last_error().map(|e| e.to_string()) } else { None };
+            (code, out, message)
+        }).collect();
+
+        let encoded: Box<[_]> = encoded.into_iter().map(|(code, out, message)| FfiAvifBatchResult {
+            code,
+            output: out.map_or(ptr::null_mut(), FfiAvifBuffer::new),
+            message: message.map_or(ptr::null_mut(), |msg| CString::new(msg).unwrap_or_default().into_raw()),
+        }).collect();
+        *results = Box::into_raw(encoded) as *mut FfiAvifBatchResult;
+        Ok(())
+    })
+}
+
+/// Release results of `ffiavif_encoder_encode_batch()`, including their buffers and messages.
+///
+/// `count` must be the same as given to `ffiavif_encoder_encode_batch()`. Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_batch_results_free(results: *mut FfiAvifBatchResult, count: usize) {
+    catch_panic((), || {
+        if results.is_null() {
+            return;
+        }
+        let results = Box::from_raw(ptr::slice_from_raw_parts_mut(results, count));
+        for res in results.iter() {
+            ffiavif_buffer_free(res.output);
+            if !res.message.is_null() {
+                drop(CString::from_raw(res.message));
+            }
+        }
+    })
+}
+
+/// Decode a PNG or JPEG file and encode it as AVIF, using settings from a JSON object.
+///
+/// The JSON object can have any subset of the fields of `ravif::Config`, for example
+/// `{"quality": 70, "speed": 6, "color_space": "rgb", "limits": {"max_pixels": 50000000}}`.
+/// Missing fields use the default settings. Unknown fields are an error.
+///
+/// On success `*output` is set to a buffer that must be released with `ffiavif_buffer_free()`.
+///
+/// Returns `FfiAvifErrorCode::Ok` on success, or `FfiAvifErrorCode::InvalidArgument` if the JSON
+/// is invalid. Details of the error can be read with `last_error_message()`.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_encode_with_json_config(data: *const c_char, data_size: usize, json: *const c_char, output: *mut *mut FfiAvifBuffer, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    ffi_result(error, || {
+        if data.is_null() {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::NullInput, "No input data pointer provided"));
+        }
+        if json.is_null() {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::NullInput, "No JSON config provided"));
+        }
+        if output.is_null() {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::NullInput, "No output pointer provided"));
+        }
+
+        let json = CStr::from_ptr(json).to_str()
+            .map_err(|e| FfiAvifError::with_source(FfiAvifErrorCode::InvalidArgument, "JSON config is not valid UTF-8", e))?;
+        let config = config_from_json(json)?;
+
+        let buffer: &[u8] = std::slice::from_raw_parts(data as *const u8, data_size);
+
+        *output = encode_file(buffer, &config)?;
+        Ok(())
+    })
+}
+
+fn config_from_json(json: &str) -> Result<Config, FfiAvifError> {
+    let config: Config = serde_json::from_str(json)
+        .map_err(|e| FfiAvifError::with_source(FfiAvifErrorCode::InvalidArgument, &format!("Invalid JSON config: {}", e), e))?;
+
+    config.validate()?;
+    Ok(config)
+}
+
+fn encode_file(data: &[u8], config: &Config) -> Result<*mut FfiAvifBuffer, FfiAvifError> {
+    let img = load_image_limited(data, config)?;
+
+    Ok(FfiAvifBuffer::new(img.encode(config)?))
+}
+
+fn encode_img(img: Img<&[RGBA8]>, config: &Config) -> Result<*mut FfiAvifBuffer, FfiAvifError> {
+    let out = encode_rgba(img, config)?;
+
+    Ok(FfiAvifBuffer::new(out))
+}
+
+/// Decoded input file
+pub enum InputImage {
+    Rgba8(ImgVec<RGBA8>),
+    /// Only for 16-bit PNG files loaded with `keep_16_bit`
+    Rgba16(ImgVec<RGBA16>),
+}
+
+impl InputImage {
+    fn encode(&self, config: &Config) -> Result<EncodedImage, ravif::Error> {
+        match self {
+            Self::Rgba8(img) => encode_rgba(img.as_ref(), config),
+            Self::Rgba16(img) => encode_rgba16(img.as_ref(), config),
+        }
     }
 }
 
-impl fmt::Display for FfiAvifError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f,"{}",self.details)
+/// Approximate memory used per pixel by the decoded image and the decoder's own buffers
+const DECODER_BYTES_PER_PIXEL: usize = 8;
+
+/// Same as [`DECODER_BYTES_PER_PIXEL`], for 16-bit PNG files decoded with `keep_16_bit`
+const DECODER_BYTES_PER_PIXEL_16: usize = 16;
+
+/// Decode a PNG or JPEG file for encoding with `config`, after checking the image size in the file header
+/// against `config.limits`. 16-bit PNG files keep their precision if `config.bit_depth` is higher than 8.
+pub fn load_image_limited(data: &[u8], config: &Config) -> Result<InputImage, FfiAvifError> {
+    let mut keep_16_bit = false;
+    match probe(data) {
+        Ok(info) => {
+            // 8-bit AVIF can't keep the extra precision anyway
+            keep_16_bit = info.bit_depth > 8 && config.bit_depth > 8;
+            let bytes_per_pixel = if keep_16_bit { DECODER_BYTES_PER_PIXEL_16 } else { DECODER_BYTES_PER_PIXEL };
+            let decoded_memory = info.width.saturating_mul(info.height).saturating_mul(bytes_per_pixel);
+            config.limits.check(info.width, info.height, config.bit_depth, config.uses_sharp_yuv(), decoded_memory)?;
+        },
+        // cocoa_image supports more formats than probe(). These are checked by the encoder after decoding.
+        Err(_) if cfg!(feature = "cocoa_image") => {},
+        Err(err) => return Err(err),
     }
+    load_image(data, config.premultiplied_alpha, keep_16_bit)
 }
 
-impl std::error::Error for FfiAvifError {
-    fn description(&self) -> &str {
-        &self.details
+/// Decode a PNG or JPEG file. If `keep_16_bit` is set, 16-bit PNG files are decoded without losing precision.
+#[cfg(not(feature = "cocoa_image"))]
+pub fn load_image(mut data: &[u8], premultiplied_alpha: bool, keep_16_bit: bool) -> Result<InputImage, FfiAvifError> {
+    use rgb::FromSlice;
+
+    let mut img = if data.get(0..4) == Some(&[0x89,b'P',b'N',b'G']) {
+        let mut decoder = lodepng::Decoder::new();
+        decoder.inspect(data).map_err(png_error)?;
+        if keep_16_bit && decoder.info_png().color.bitdepth() > 8 {
+            return load_png16(data, premultiplied_alpha).map(InputImage::Rgba16);
+        }
+        let img = lodepng::decode32(data).map_err(png_error)?;
+        ImgVec::new(img.buffer, img.width, img.height)
+    } else if data.get(0..2) == Some(&[0xFF, 0xD8]) {
+        let mut jecoder = jpeg_decoder::Decoder::new(&mut data);
+        let pixels = jecoder.decode()
+            .map_err(|e| FfiAvifError::with_source(FfiAvifErrorCode::DecoderFailure, &format!("Unable to decode JPEG: {}", e), e))?;
+        let info = jecoder.info().ok_or_else(|| FfiAvifError::new(FfiAvifErrorCode::DecoderFailure, "Error reading JPEG info"))?;
+        use jpeg_decoder::PixelFormat::*;
+        let buf: Vec<_> = match info.pixel_format {
+            L8 => {
+                pixels.iter().copied().map(|g| RGBA8::new(g,g,g,255)).collect()
+            },
+            RGB24 => {
+                let rgb = pixels.as_rgb();
+                rgb.iter().map(|p| p.alpha(255)).collect()
+            },
+            CMYK32 => return Err(FfiAvifError::new(FfiAvifErrorCode::CmykJpeg, "CMYK JPEG is not supported. Please convert to PNG first")),
+        };
+        ImgVec::new(buf, info.width.into(), info.height.into())
+    } else {
+        return Err(FfiAvifError::new(FfiAvifErrorCode::UnsupportedFormat, "Unsupported image format. Only PNG and JPEG are supported"));
+    };
+    if premultiplied_alpha {
+        img.pixels_mut().for_each(|px| {
+            px.r = (px.r as u16 * px.a as u16 / 255) as u8;
+            px.g = (px.g as u16 * px.a as u16 / 255) as u8;
+            px.b = (px.b as u16 * px.a as u16 / 255) as u8;
+        });
+    }
+    Ok(InputImage::Rgba8(img))
+}
+
+#[cfg(not(feature = "cocoa_image"))]
+fn load_png16(data: &[u8], premultiplied_alpha: bool) -> Result<ImgVec<RGBA16>, FfiAvifError> {
+    use rgb::ComponentMap;
+
+    let mut img = match lodepng::decode_memory(data, lodepng::ColorType::RGBA, 16).map_err(png_error)? {
+        lodepng::Image::RGBA16(img) => img,
+        _ => return Err(FfiAvifError::new(FfiAvifErrorCode::DecoderFailure, "Unable to decode PNG as 16-bit RGBA")),
+    };
+    // lodepng gives samples in PNG's big-endian byte order. Converted in place to avoid a second copy of the image.
+    img.buffer.iter_mut().for_each(|px| *px = px.map(u16::from_be));
+    let mut img = ImgVec::new(img.buffer, img.width, img.height);
+    if premultiplied_alpha {
+        img.pixels_mut().for_each(|px| {
+            px.r = (px.r as u32 * px.a as u32 / 65535) as u16;
+            px.g = (px.g as u32 * px.a as u32 / 65535) as u16;
+            px.b = (px.b as u32 * px.a as u32 / 65535) as u16;
+        });
     }
-}
\ No newline at end of file
+    Ok(img)
+}
+
+#[cfg(not(feature = "cocoa_image"))]
+fn png_error(e: lodepng::Error) -> FfiAvifError {
+    // lodepng reports failed allocations as error 83
+    let kind = if lodepng::ffi::ErrorCode::from(e).0 == 83 { FfiAvifErrorCode::OutOfMemory } else { FfiAvifErrorCode::DecoderFailure };
+    FfiAvifError::with_source(kind, &format!("Unable to decode PNG: {}", e), e)
+}
+
+/// Decode an image file with the system decoder, which always gives 8-bit pixels
+#[cfg(feature = "cocoa_image")]
+pub fn load_image(data: &[u8], premultiplied_alpha: bool, _keep_16_bit: bool) -> Result<InputImage, FfiAvifError> {
+    let img = if premultiplied_alpha {
+        cocoa_image::decode_image_as_rgba_premultiplied(data)
+    } else {
+        cocoa_image::decode_image_as_rgba(data)
+    };
+    img.map(InputImage::Rgba8).map_err(|e| FfiAvifError::with_source(FfiAvifErrorCode::DecoderFailure, &format!("Unable to decode the image: {}", e), e))
+}
@@ -0,0 +1,107 @@
+use crate::*;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::ffi::CString;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+/// Severity of a log message, and the maximum severity to log
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FfiAvifLogLevel {
+    Off = 0,
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+/// Receives log messages of the library (and of the encoder it uses).
+///
+/// `target` is the module that logged the message. Both strings are nul-terminated UTF-8,
+/// and are valid only during the call. The callback may be called from any thread.
+pub type FfiAvifLogCallback = extern "C" fn(level: FfiAvifLogLevel, target: *const c_char, message: *const c_char, user_data: *mut c_void);
+
+struct FfiLogger {
+    callback: RwLock<Option<(FfiAvifLogCallback, UserData)>>,
+}
+
+static LOGGER: FfiLogger = FfiLogger {
+    callback: RwLock::new(None),
+};
+
+/// Whether the host has chosen the level with `ffiavif_set_log_max_level()`, so the default mustn't override it
+static MAX_LEVEL_SET: AtomicBool = AtomicBool::new(false);
+
+impl Log for FfiLogger {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        matches!(self.callback.read().as_deref(), Ok(Some(_)))
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        // Copied out so that the callback can call ffiavif_set_log_callback() without deadlocking
+        let callback = match self.callback.read() {
+            Ok(cb) => *cb,
+            Err(_) => return,
+        };
+        if let Some((callback, user_data)) = callback {
+            let level = match record.level() {
+                Level::Error => FfiAvifLogLevel::Error,
+                Level::Warn => FfiAvifLogLevel::Warn,
+                Level::Info => FfiAvifLogLevel::Info,
+                Level::Debug => FfiAvifLogLevel::Debug,
+                Level::Trace => FfiAvifLogLevel::Trace,
+            };
+            let target = c_string(record.target());
+            let message = c_string(&record.args().to_string());
+            callback(level, target.as_ptr(), message.as_ptr(), user_data.0);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn c_string(s: &str) -> CString {
+    CString::new(s.replace('\0', "")).unwrap_or_default()
+}
+
+/// Send the library's log messages to a callback, or stop logging by passing null.
+///
+/// `user_data` is passed to the callback unchanged. Unless `ffiavif_set_log_max_level()` has been
+/// called before, messages up to `FfiAvifLogLevel::Warn` are logged.
+///
+/// When the library is linked into a Rust program that has installed its own logger,
+/// that logger keeps receiving the messages instead.
+///
+/// Returns `FfiAvifErrorCode::Ok` on success.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_set_log_callback(callback: Option<FfiAvifLogCallback>, user_data: *mut c_void, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    ffi_result(error, || {
+        *LOGGER.callback.write().unwrap_or_else(|e| e.into_inner()) = callback.map(|cb| (cb, UserData(user_data)));
+        if callback.is_some() && log::set_logger(&LOGGER).is_ok() && !MAX_LEVEL_SET.load(Ordering::Relaxed) && log::max_level() == LevelFilter::Off {
+            log::set_max_level(LevelFilter::Warn);
+        }
+        Ok(())
+    })
+}
+
+/// Set the most verbose level of messages sent to the log callback,
+/// from `0` (`FfiAvifLogLevel::Off`) to `5` (`FfiAvifLogLevel::Trace`).
+///
+/// Returns `FfiAvifErrorCode::Ok` on success.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_set_log_max_level(level: c_int, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    ffi_result(error, || {
+        log::set_max_level(match level {
+            0 => LevelFilter::Off,
+            1 => LevelFilter::Error,
+            2 => LevelFilter::Warn,
+            3 => LevelFilter::Info,
+            4 => LevelFilter::Debug,
+            5 => LevelFilter::Trace,
+            _ => return Err(FfiAvifError::new(FfiAvifErrorCode::InvalidArgument, "Log level must be between 0 and 5")),
+        });
+        MAX_LEVEL_SET.store(true, Ordering::Relaxed);
+        Ok(())
+    })
+}
@@ -1,7 +1,5 @@
-mod lib;
-
 use clap::{Arg, App, AppSettings, value_t};
-use imgref::ImgVec;
+use ffiavif::{load_image_limited, InputImage};
 use rayon::prelude::*;
 use std::fs;
 use std::io::Read;
@@ -81,8 +79,46 @@ fn run() -> Result<(), BoxError> {
             .long("color")
             .default_value("ycbcr")
             .takes_value(true)
-            .possible_values(&["ycbcr", "rgb"])
+            .possible_values(ColorSpace::NAMES)
             .help("Internal AVIF color space"))
+        .arg(Arg::with_name("matrix")
+            .long("matrix")
+            .default_value("bt709")
+            .takes_value(true)
+            .possible_values(MatrixCoefficients::NAMES)
+            .help("How RGB is converted to YCbCr"))
+        .arg(Arg::with_name("primaries")
+            .long("primaries")
+            .default_value("bt709")
+            .takes_value(true)
+            .possible_values(ColorPrimaries::NAMES)
+            .help("Color primaries of the input images. Pixels are only labelled, not converted"))
+        .arg(Arg::with_name("transfer")
+            .long("transfer")
+            .default_value("srgb")
+            .takes_value(true)
+            .possible_values(TransferCharacteristics::NAMES)
+            .help("Transfer function of the input images. Pixels are only labelled, not converted"))
+        .arg(Arg::with_name("depth")
+            .long("depth")
+            .default_value("8")
+            .takes_value(true)
+            .possible_values(&["8", "10", "12"])
+            .help("Bits per channel. Higher depths prevent banding in gradients, but make larger files"))
+        .arg(Arg::with_name("chroma-subsampling")
+            .long("chroma-subsampling")
+            .default_value("444")
+            .takes_value(true)
+            .possible_values(ChromaSubsampling::NAMES)
+            .help("Resolution of color. 420 makes smaller files, but blurs colors. Requires --color=ycbcr"))
+        .arg(Arg::with_name("sharp-yuv")
+            .long("sharp-yuv")
+            .help("Slower color conversion that keeps edges sharper with --chroma-subsampling=420 or 422"))
+        .arg(Arg::with_name("config")
+            .long("config")
+            .value_name("file.json")
+            .takes_value(true)
+            .help("Load encoder settings from a JSON file. Options given on the command line take precedence."))
         .arg(Arg::with_name("IMAGES")
             .index(1)
             .help("One or more JPEG or PNG files to convert. \"-\" is interpreted as stdin/stdout.")
@@ -103,11 +139,64 @@ fn run() -> Result<(), BoxError> {
     let threads: usize = value_t!(args, "threads", usize)?;
     let dirty_alpha = args.is_present("dirty-alpha");
 
-    let color_space = match args.value_of("color").expect("default") {
-        "ycbcr" => ColorSpace::YCbCr,
-        "rgb" => ColorSpace::RGB,
-        x => Err(format!("bad color type: {}", x))?,
+    let color_space = value_t!(args, "color", ColorSpace)?;
+    let matrix_coefficients = value_t!(args, "matrix", MatrixCoefficients)?;
+    let color_primaries = value_t!(args, "primaries", ColorPrimaries)?;
+    let transfer_characteristics = value_t!(args, "transfer", TransferCharacteristics)?;
+
+    let bit_depth = value_t!(args, "depth", u8)?;
+    let chroma_subsampling = value_t!(args, "chroma-subsampling", ChromaSubsampling)?;
+
+    let sharp_yuv = args.is_present("sharp-yuv");
+
+    let config_path = args.value_of_os("config").map(PathBuf::from);
+    let mut config: Config = match config_path {
+        Some(ref path) => {
+            let json = fs::read_to_string(path)
+                .map_err(|e| format!("Unable to read config file {}: {}", path.display(), e))?;
+            serde_json::from_str(&json)
+                .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?
+        },
+        None => Config::default(),
     };
+    // Settings from the config file are overridden only by options that were given explicitly
+    let explicit = |name| config_path.is_none() || args.occurrences_of(name) > 0;
+    if explicit("quality") {
+        config.quality = quality;
+        config.alpha_quality = alpha_quality;
+    }
+    if explicit("speed") {
+        config.speed = speed;
+    }
+    if explicit("threads") {
+        config.threads = threads;
+    }
+    if explicit("color") {
+        config.color_space = color_space;
+    }
+    if explicit("matrix") {
+        config.matrix_coefficients = matrix_coefficients;
+    }
+    if explicit("primaries") {
+        config.color_primaries = color_primaries;
+    }
+    if explicit("transfer") {
+        config.transfer_characteristics = transfer_characteristics;
+    }
+    if explicit("depth") {
+        config.bit_depth = bit_depth;
+    }
+    if explicit("chroma-subsampling") {
+        config.chroma_subsampling = chroma_subsampling;
+    }
+    if explicit("sharp-yuv") {
+        config.sharp_yuv = sharp_yuv;
+    }
+    config.validate().map_err(|e| match config_path {
+        Some(ref path) => format!("Invalid settings in {}: {}", path.display(), e),
+        None => e.to_string(),
+    })?;
+
     let files = args.values_of_os("IMAGES").ok_or("Please specify image paths to convert")?;
     let files: Vec<_> = files
         .filter(|pathstr| {
@@ -147,7 +236,7 @@ fn run() -> Result<(), BoxError> {
     };
 
     let process = move |data: Vec<u8>, input_path: &MaybePath| -> Result<(), BoxError> {
-        let mut img = load_rgba(&data, false)?;
+        let img = load_image_limited(&data, &config)?;
         drop(data);
         let out_path = match (&output, input_path) {
             (None, MaybePath::Path(input)) => MaybePath::Path(input.with_extension("avif")),
@@ -168,15 +257,11 @@ fn run() -> Result<(), BoxError> {
             },
             _ => {},
         }
-        if !dirty_alpha {
-            img = cleared_alpha(img);
-        }
-        let (out_data, color_size, alpha_size) = encode_rgba(img.as_ref(), &Config {
-            quality, speed,
-            alpha_quality,
-            premultiplied_alpha: false,
-            color_space, threads,
-        })?;
+        let encoded = match img {
+            InputImage::Rgba8(img) => encode_rgba(if dirty_alpha { img } else { cleared_alpha(img) }.as_ref(), &config)?,
+            InputImage::Rgba16(img) => encode_rgba16(if dirty_alpha { img } else { cleared_alpha16(img) }.as_ref(), &config)?,
+        };
+        let EncodedImage { avif_file: out_data, color_byte_size: color_size, alpha_byte_size: alpha_size, .. } = encoded;
         match out_path {
             MaybePath::Path(ref p) => {
                 if !quiet {
@@ -222,46 +307,3 @@ fn run() -> Result<(), BoxError> {
     }
     Ok(())
 }
-
-#[cfg(not(feature = "cocoa_image"))]
-fn load_rgba(mut data: &[u8], premultiplied_alpha: bool) -> Result<ImgVec<RGBA8>, BoxError> {
-    use rgb::FromSlice;
-
-    let mut img = if data.get(0..4) == Some(&[0x89,b'P',b'N',b'G']) {
-        let img = lodepng::decode32(data)?;
-        ImgVec::new(img.buffer, img.width, img.height)
-    } else {
-        let mut jecoder = jpeg_decoder::Decoder::new(&mut data);
-        let pixels = jecoder.decode()?;
-        let info = jecoder.info().ok_or("Error reading JPEG info")?;
-        use jpeg_decoder::PixelFormat::*;
-        let buf: Vec<_> = match info.pixel_format {
-            L8 => {
-                pixels.iter().copied().map(|g| RGBA8::new(g,g,g,255)).collect()
-            },
-            RGB24 => {
-                let rgb = pixels.as_rgb();
-                rgb.iter().map(|p| p.alpha(255)).collect()
-            },
-            CMYK32 => return Err("CMYK JPEG is not supported. Please convert to PNG first".into()),
-        };
-        ImgVec::new(buf, info.width.into(), info.height.into())
-    };
-    if premultiplied_alpha {
-        img.pixels_mut().for_each(|px| {
-            px.r = (px.r as u16 * px.a as u16 / 255) as u8;
-            px.g = (px.g as u16 * px.a as u16 / 255) as u8;
-            px.b = (px.b as u16 * px.a as u16 / 255) as u8;
-        });
-    }
-    Ok(img)
-}
-
-#[cfg(feature = "cocoa_image")]
-fn load_rgba(data: &[u8], premultiplied_alpha: bool) -> Result<ImgVec<RGBA8>, BoxError> {
-    if premultiplied_alpha {
-        Ok(cocoa_image::decode_image_as_rgba_premultiplied(data)?)
-    } else {
-        Ok(cocoa_image::decode_image_as_rgba(data)?)
-    }
-}
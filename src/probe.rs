@@ -0,0 +1,77 @@
+use crate::*;
+
+/// File format of an input image
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FfiAvifSourceFormat {
+    Png = 1,
+    Jpeg = 2,
+}
+
+/// Basic properties of an input image, read from its header
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FfiAvifImageInfo {
+    pub width: usize,
+    pub height: usize,
+    pub format: FfiAvifSourceFormat,
+    /// The file can have transparent pixels. The pixels aren't checked, so they may
+    /// all turn out to be opaque.
+    pub has_alpha: bool,
+    /// Bits per channel (or per palette index, for palette PNGs)
+    pub bit_depth: u8,
+    /// JPEG in CMYK color space, which can't be encoded
+    pub is_cmyk: bool,
+}
+
+/// Read the size and format of a PNG or JPEG file, without decoding its pixels.
+///
+/// On success `*info` is filled in.
+///
+/// Returns `FfiAvifErrorCode::Ok` on success. Details of the error can be read with `last_error_message()`.
+#[no_mangle]
+pub unsafe extern "C" fn ffiavif_probe(data: *const c_char, data_size: usize, info: *mut FfiAvifImageInfo, error: *mut *mut FfiAvifErrorInfo) -> FfiAvifErrorCode {
+    ffi_result(error, || {
+        if data.is_null() {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::NullInput, "No input data pointer provided"));
+        }
+        if info.is_null() {
+            return Err(FfiAvifError::new(FfiAvifErrorCode::NullInput, "No info pointer provided"));
+        }
+
+        *info = probe(slice::from_raw_parts(data as *const u8, data_size))?;
+        Ok(())
+    })
+}
+
+pub(crate) fn probe(mut data: &[u8]) -> Result<FfiAvifImageInfo, FfiAvifError> {
+    if data.get(0..4) == Some(&[0x89,b'P',b'N',b'G']) {
+        let mut decoder = lodepng::Decoder::new();
+        let (width, height) = decoder.inspect(data)
+            .map_err(|e| FfiAvifError::with_source(FfiAvifErrorCode::DecoderFailure, &format!("Unable to read PNG header: {}", e), e))?;
+        let color = &decoder.info_png().color;
+        Ok(FfiAvifImageInfo {
+            width,
+            height,
+            format: FfiAvifSourceFormat::Png,
+            has_alpha: color.can_have_alpha(),
+            bit_depth: color.bitdepth() as u8,
+            is_cmyk: false,
+        })
+    } else if data.get(0..2) == Some(&[0xFF, 0xD8]) {
+        let mut jecoder = jpeg_decoder::Decoder::new(&mut data);
+        jecoder.read_info()
+            .map_err(|e| FfiAvifError::with_source(FfiAvifErrorCode::DecoderFailure, &format!("Unable to read JPEG header: {}", e), e))?;
+        let info = jecoder.info().ok_or_else(|| FfiAvifError::new(FfiAvifErrorCode::DecoderFailure, "Error reading JPEG info"))?;
+        Ok(FfiAvifImageInfo {
+            width: info.width.into(),
+            height: info.height.into(),
+            format: FfiAvifSourceFormat::Jpeg,
+            has_alpha: false,
+            bit_depth: 8,
+            is_cmyk: info.pixel_format == jpeg_decoder::PixelFormat::CMYK32,
+        })
+    } else {
+        Err(FfiAvifError::new(FfiAvifErrorCode::UnsupportedFormat, "Unsupported image format. Only PNG and JPEG are supported"))
+    }
+}
@@ -0,0 +1,42 @@
+use std::os::raw::c_char;
+
+/// Incremented on every incompatible change of the exported functions or `#[repr(C)]` types.
+/// Hosts should refuse to use the library if it's not the version they were built for.
+pub const FFIAVIF_ABI_VERSION: u32 = 1;
+
+/// Built with assembly-optimized rav1e (the `asm` feature)
+pub const FFIAVIF_CAP_ASM: u32 = 1 << 0;
+/// Can decode PNG input files
+pub const FFIAVIF_CAP_DECODE_PNG: u32 = 1 << 1;
+/// Can decode JPEG input files
+pub const FFIAVIF_CAP_DECODE_JPEG: u32 = 1 << 2;
+/// Decodes input files with the system's image decoder (the `cocoa_image` feature),
+/// which supports more formats than PNG and JPEG
+pub const FFIAVIF_CAP_DECODE_SYSTEM: u32 = 1 << 3;
+/// `ffiavif_probe()` can read metadata of PNG and JPEG files
+pub const FFIAVIF_CAP_PROBE: u32 = 1 << 4;
+
+/// Version of the library, as a nul-terminated string like `"1.0.0"`. Don't free it.
+#[no_mangle]
+pub extern "C" fn ffiavif_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+/// Same as `FFIAVIF_ABI_VERSION` of the library that is actually loaded.
+#[no_mangle]
+pub extern "C" fn ffiavif_abi_version() -> u32 {
+    FFIAVIF_ABI_VERSION
+}
+
+/// Bitmask of the `FFIAVIF_CAP_*` features that the library has been built with.
+#[no_mangle]
+pub extern "C" fn ffiavif_capabilities() -> u32 {
+    let mut caps = FFIAVIF_CAP_DECODE_PNG | FFIAVIF_CAP_DECODE_JPEG | FFIAVIF_CAP_PROBE;
+    if cfg!(feature = "asm") {
+        caps |= FFIAVIF_CAP_ASM;
+    }
+    if cfg!(feature = "cocoa_image") {
+        caps |= FFIAVIF_CAP_DECODE_SYSTEM;
+    }
+    caps
+}
@@ -0,0 +1,705 @@
+use ffiavif::*;
+
+#[test]
+fn encode_file_to_buffer() {
+    let img = include_bytes!("testimage.png");
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_speed(enc, 10, std::ptr::null_mut()));
+
+        let mut buf = std::ptr::null_mut();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode(enc, img.as_ptr() as *const _, img.len(), &mut buf, std::ptr::null_mut()));
+        assert!(!buf.is_null());
+        assert!((*buf).capacity >= (*buf).len);
+        let data = std::slice::from_raw_parts((*buf).data, (*buf).len);
+        assert_eq!(&data[4..4+8], b"ftypavif");
+
+        let stats = (*buf).stats;
+        assert!(stats.color_bytes > 0);
+        assert_eq!((*buf).len, stats.color_bytes + stats.alpha_bytes + stats.container_bytes);
+        assert_eq!(stats.has_alpha, stats.alpha_bytes > 0);
+
+        ffiavif_buffer_free(buf);
+        ffiavif_encoder_free(enc);
+    }
+}
+
+#[test]
+fn copy_into_caller_memory() {
+    let img = include_bytes!("testimage.png");
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_speed(enc, 10, std::ptr::null_mut()));
+
+        let mut buf = std::ptr::null_mut();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode(enc, img.as_ptr() as *const _, img.len(), &mut buf, std::ptr::null_mut()));
+        let mut dst = vec![0u8; (*buf).len];
+        assert_eq!(FfiAvifErrorCode::BufferTooSmall, ffiavif_buffer_take(buf, dst.as_mut_ptr(), dst.len() - 1, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_buffer_take(buf, dst.as_mut_ptr(), dst.len(), std::ptr::null_mut()));
+        assert_eq!(&dst[4..4+8], b"ftypavif");
+        ffiavif_encoder_free(enc);
+    }
+}
+
+#[test]
+fn encode_rgba_pixels_with_stride() {
+    let (width, height, stride) = (7, 5, 8 * 4);
+    let pixels: Vec<u8> = (0..stride * height).map(|i| (i * 7) as u8).collect();
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_speed(enc, 10, std::ptr::null_mut()));
+
+        let mut buf = std::ptr::null_mut();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode_rgba(enc, pixels.as_ptr(), width, height, stride, &mut buf, std::ptr::null_mut()));
+        assert!(!buf.is_null());
+        let data = std::slice::from_raw_parts((*buf).data, (*buf).len);
+        assert_eq!(&data[4..4+8], b"ftypavif");
+        ffiavif_buffer_free(buf);
+
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_encoder_encode_rgba(enc, pixels.as_ptr(), width, height, width * 4 - 1, &mut buf, std::ptr::null_mut()));
+        assert!(last_error_length() > 0);
+
+        ffiavif_encoder_free(enc);
+    }
+}
+
+#[test]
+#[allow(deprecated)]
+fn deprecated_wrappers() {
+    let config = LegacyConfig {
+        quality: 80.,
+        alpha_quality: 90.,
+        speed: 10,
+        premultiplied_alpha: false,
+        color_space: ravif::ColorSpace::YCbCr,
+        threads: 0,
+    };
+    // Layout used by C callers: two floats, two bytes, a C enum and a size_t
+    #[cfg(target_pointer_width = "64")]
+    assert_eq!(24, std::mem::size_of::<LegacyConfig>());
+    let (width, height) = (4, 3);
+    let pixels = vec![200u8; width * height * 4];
+    unsafe {
+        let buf = enc_rgba_pixels(pixels.as_ptr(), width, height, width * 4, &config);
+        assert!(!buf.is_null());
+        assert_eq!(&std::slice::from_raw_parts((*buf).data, (*buf).len)[4..4+8], b"ftypavif");
+        ffiavif_buffer_free(buf);
+
+        assert!(enc_rgba_pixels(pixels.as_ptr(), width, height, 3, &config).is_null());
+        assert!(last_error_length() > 0);
+
+        let img = include_bytes!("testimage.png");
+        let buf = enc_rgba(img.as_ptr().cast(), img.len(), &config);
+        assert!(!buf.is_null());
+        ffiavif_buffer_free(buf);
+    }
+}
+
+#[test]
+fn error_codes() {
+    let not_an_image = b"GIF89a";
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        let mut buf = std::ptr::null_mut();
+        assert_eq!(FfiAvifErrorCode::UnsupportedFormat, ffiavif_encoder_encode(enc, not_an_image.as_ptr() as *const _, not_an_image.len(), &mut buf, std::ptr::null_mut()));
+        assert!(buf.is_null());
+        assert_eq!(FfiAvifErrorCode::NullInput, ffiavif_encoder_encode(enc, std::ptr::null(), 0, &mut buf, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_encoder_set_quality(enc, 101., std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::NullInput, ffiavif_encoder_set_speed(std::ptr::null_mut(), 1, std::ptr::null_mut()));
+        ffiavif_encoder_free(enc);
+    }
+}
+
+extern "C" fn record_progress(progress: f32, user_data: *mut std::os::raw::c_void) {
+    let reports = unsafe { &*(user_data as *const std::sync::Mutex<Vec<f32>>) };
+    reports.lock().unwrap().push(progress);
+}
+
+#[test]
+fn progress_and_cancel() {
+    let img = include_bytes!("testimage.png");
+    let reports = std::sync::Mutex::new(Vec::<f32>::new());
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_speed(enc, 10, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_progress_callback(enc, Some(record_progress), &reports as *const _ as *mut _, std::ptr::null_mut()));
+
+        let mut buf = std::ptr::null_mut();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_cancel(enc, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Cancelled, ffiavif_encoder_encode(enc, img.as_ptr() as *const _, img.len(), &mut buf, std::ptr::null_mut()));
+        assert!(buf.is_null());
+
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_reset_cancel(enc, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode(enc, img.as_ptr() as *const _, img.len(), &mut buf, std::ptr::null_mut()));
+        ffiavif_buffer_free(buf);
+        ffiavif_encoder_free(enc);
+    }
+    let reports = reports.into_inner().unwrap();
+    assert_eq!(Some(&0.), reports.first());
+    assert_eq!(Some(&1.), reports.last());
+    assert!(reports.windows(2).all(|w| w[0] <= w[1]));
+}
+
+#[test]
+fn cancel_while_encoding() {
+    // Noise at the slowest speed takes much longer to encode than the test waits
+    let (width, height) = (512, 512);
+    let pixels: Vec<u8> = (0..width * height * 4).map(|i: usize| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_speed(enc, 0, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_threads(enc, 1, std::ptr::null_mut()));
+
+        let enc_addr = enc as usize;
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(300));
+            assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_cancel(enc_addr as *const _, std::ptr::null_mut()));
+            std::time::Instant::now()
+        });
+        let mut buf = std::ptr::null_mut();
+        assert_eq!(FfiAvifErrorCode::Cancelled, ffiavif_encoder_encode_rgba(enc, pixels.as_ptr(), width, height, width * 4, &mut buf, std::ptr::null_mut()));
+        let cancelled_at = canceller.join().unwrap();
+        assert!(cancelled_at.elapsed() < std::time::Duration::from_secs(1), "{:?}", cancelled_at.elapsed());
+        ffiavif_encoder_free(enc);
+    }
+}
+
+type JobResult = (u64, FfiAvifErrorCode, Option<usize>, String);
+
+extern "C" fn job_done(job_id: u64, code: FfiAvifErrorCode, output: *mut FfiAvifBuffer, user_data: *mut std::os::raw::c_void) {
+    let sender = unsafe { &*(user_data as *const std::sync::Mutex<std::sync::mpsc::Sender<JobResult>>) };
+    let mut message = vec![0u8; 256];
+    let len = unsafe { last_error_message(message.as_mut_ptr() as *mut _, message.len() as _) };
+    message.truncate(len.max(0) as usize);
+    let len = unsafe { output.as_ref() }.map(|buf| buf.len);
+    unsafe { ffiavif_buffer_free(output) };
+    sender.lock().unwrap().send((job_id, code, len, String::from_utf8(message).unwrap())).unwrap();
+}
+
+#[test]
+fn encode_async() {
+    let img = include_bytes!("testimage.png");
+    let (sender, receiver) = std::sync::mpsc::channel::<JobResult>();
+    let sender = std::sync::Mutex::new(sender);
+    let user_data = &sender as *const _ as *mut _;
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_speed(enc, 10, std::ptr::null_mut()));
+
+        let (mut good_id, mut bad_id) = (0, 0);
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encode_async(enc, img.as_ptr() as *const _, img.len(), Some(job_done), user_data, &mut good_id, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encode_async(enc, b"GIF89a".as_ptr() as *const _, 6, Some(job_done), user_data, &mut bad_id, std::ptr::null_mut()));
+        assert_ne!(good_id, bad_id);
+        // The encoder isn't needed by queued jobs
+        ffiavif_encoder_free(enc);
+
+        let mut results: Vec<_> = receiver.iter().take(2).collect();
+        results.sort_by_key(|r| r.0);
+        let (id, code, len, _) = &results[0];
+        assert_eq!((good_id, FfiAvifErrorCode::Ok), (*id, *code));
+        assert!(len.unwrap() > 0);
+        let (id, code, len, message) = &results[1];
+        assert_eq!((bad_id, FfiAvifErrorCode::UnsupportedFormat, None), (*id, *code, *len));
+        assert!(!message.is_empty());
+
+        // Finished jobs can't be cancelled
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_cancel_job(good_id, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::NullInput, ffiavif_encode_async(std::ptr::null(), img.as_ptr() as *const _, img.len(), Some(job_done), user_data, std::ptr::null_mut(), std::ptr::null_mut()));
+    }
+}
+
+#[test]
+fn encode_batch() {
+    let img = include_bytes!("testimage.png");
+    let gif = b"GIF89a";
+    let inputs = [
+        FfiAvifInput { data: img.as_ptr() as *const _, len: img.len() },
+        FfiAvifInput { data: gif.as_ptr() as *const _, len: gif.len() },
+        FfiAvifInput { data: std::ptr::null(), len: 0 },
+        FfiAvifInput { data: img.as_ptr() as *const _, len: img.len() },
+    ];
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_speed(enc, 10, std::ptr::null_mut()));
+
+        let mut results = std::ptr::null_mut();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode_batch(enc, inputs.as_ptr(), inputs.len(), &mut results, std::ptr::null_mut()));
+        let res = std::slice::from_raw_parts_mut(results, inputs.len());
+        let codes: Vec<_> = res.iter().map(|r| r.code).collect();
+        assert_eq!(codes, [FfiAvifErrorCode::Ok, FfiAvifErrorCode::UnsupportedFormat, FfiAvifErrorCode::NullInput, FfiAvifErrorCode::Ok]);
+        assert!(res[0].message.is_null());
+        assert!(res[1].output.is_null());
+        assert!(!std::ffi::CStr::from_ptr(res[1].message).to_bytes().is_empty());
+
+        // Buffers can outlive the results
+        let kept = std::mem::replace(&mut res[3].output, std::ptr::null_mut());
+        ffiavif_batch_results_free(results, inputs.len());
+        assert!((*kept).len > 0);
+        ffiavif_buffer_free(kept);
+
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode_batch(enc, std::ptr::null(), 0, &mut results, std::ptr::null_mut()));
+        ffiavif_batch_results_free(results, 0);
+        ffiavif_encoder_free(enc);
+    }
+}
+
+#[test]
+fn probe_header() {
+    let img = include_bytes!("testimage.png");
+    unsafe {
+        let mut info = std::mem::MaybeUninit::<FfiAvifImageInfo>::uninit();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_probe(img.as_ptr() as *const _, img.len(), info.as_mut_ptr(), std::ptr::null_mut()));
+        let info = info.assume_init();
+        assert_eq!((128, 85), (info.width, info.height));
+        assert_eq!(FfiAvifSourceFormat::Png, info.format);
+        assert_eq!(8, info.bit_depth);
+        assert!(!info.has_alpha);
+        assert!(!info.is_cmyk);
+
+        // Pixel data is not needed
+        let mut info = std::mem::MaybeUninit::uninit();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_probe(img.as_ptr() as *const _, 813, info.as_mut_ptr(), std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::DecoderFailure, ffiavif_probe(img.as_ptr() as *const _, 20, info.as_mut_ptr(), std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::UnsupportedFormat, ffiavif_probe(b"GIF89a".as_ptr() as *const _, 6, info.as_mut_ptr(), std::ptr::null_mut()));
+    }
+}
+
+#[test]
+fn size_limits() {
+    let img = include_bytes!("testimage.png");
+    let pixels = vec![0u8; 128 * 85 * 4];
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_speed(enc, 10, std::ptr::null_mut()));
+
+        let mut buf = std::ptr::null_mut();
+        for &(max_width, max_height, max_pixels, max_memory) in &[(127, 0, 0, 0), (0, 84, 0, 0), (0, 0, 128 * 85 - 1, 0), (0, 0, 0, 100_000)] {
+            assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_limits(enc, max_width, max_height, max_pixels, max_memory, std::ptr::null_mut()));
+            assert_eq!(FfiAvifErrorCode::LimitExceeded, ffiavif_encoder_encode(enc, img.as_ptr() as *const _, img.len(), &mut buf, std::ptr::null_mut()));
+            assert_eq!(FfiAvifErrorCode::LimitExceeded, ffiavif_encoder_encode_rgba(enc, pixels.as_ptr(), 128, 85, 128 * 4, &mut buf, std::ptr::null_mut()));
+            assert!(buf.is_null());
+        }
+
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_limits(enc, 128, 85, 128 * 85, 100 << 20, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode(enc, img.as_ptr() as *const _, img.len(), &mut buf, std::ptr::null_mut()));
+        ffiavif_buffer_free(buf);
+
+        // Sharp YUV needs extra memory for its working planes
+        let max_memory = ravif::Limits::encoder_memory(128, 85, 8, false);
+        assert!(ravif::Limits::encoder_memory(128, 85, 8, true) > max_memory);
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_limits(enc, 0, 0, 0, max_memory, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_chroma_subsampling(enc, 420, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode_rgba(enc, pixels.as_ptr(), 128, 85, 128 * 4, &mut buf, std::ptr::null_mut()));
+        ffiavif_buffer_free(buf);
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_sharp_yuv(enc, true, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::LimitExceeded, ffiavif_encoder_encode_rgba(enc, pixels.as_ptr(), 128, 85, 128 * 4, &mut buf, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_sharp_yuv(enc, false, std::ptr::null_mut()));
+
+        // So do 10-bit planes
+        assert!(ravif::Limits::encoder_memory(128, 85, 10, false) > max_memory);
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_bit_depth(enc, 10, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::LimitExceeded, ffiavif_encoder_encode_rgba(enc, pixels.as_ptr(), 128, 85, 128 * 4, &mut buf, std::ptr::null_mut()));
+        let y_plane = vec![128u8; 128 * 85];
+        let uv_plane = vec![128u8; 64 * 43];
+        assert_eq!(FfiAvifErrorCode::LimitExceeded, ffiavif_encoder_encode_yuv(enc, y_plane.as_ptr(), 128, uv_plane.as_ptr(), 64, uv_plane.as_ptr(), 64, std::ptr::null(), 0, 128, 85, 1, &mut buf, std::ptr::null_mut()));
+        ffiavif_encoder_free(enc);
+    }
+}
+
+#[test]
+fn encode_yuv_planes() {
+    let (width, height) = (9, 6);
+    let y_stride = width + 3;
+    let y: Vec<u8> = (0..y_stride * height).map(|i| 16 + (i * 3 % 200) as u8).collect();
+    let u = vec![128u8; width * height];
+    let v: Vec<u8> = (0..width * height).map(|i| (100 + i) as u8).collect();
+    let alpha: Vec<u8> = (0..width * height).map(|i| (i * 4) as u8).collect();
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_speed(enc, 10, std::ptr::null_mut()));
+
+        let mut buf = std::ptr::null_mut();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode_yuv(enc, y.as_ptr(), y_stride, u.as_ptr(), width, v.as_ptr(), width, alpha.as_ptr(), width, width, height, 0, &mut buf, std::ptr::null_mut()));
+        let data = std::slice::from_raw_parts((*buf).data, (*buf).len);
+        assert_eq!(&data[4..4+8], b"ftypavif");
+        assert!((*buf).stats.has_alpha);
+        ffiavif_buffer_free(buf);
+
+        let mut buf = std::ptr::null_mut();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode_yuv(enc, y.as_ptr(), y_stride, u.as_ptr(), width, v.as_ptr(), width, std::ptr::null(), 0, width, height, 1, &mut buf, std::ptr::null_mut()));
+        assert!(!(*buf).stats.has_alpha);
+        ffiavif_buffer_free(buf);
+
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_encoder_encode_yuv(enc, y.as_ptr(), y_stride, u.as_ptr(), width, v.as_ptr(), width, std::ptr::null(), 0, width, height, 2, &mut buf, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_encoder_encode_yuv(enc, y.as_ptr(), y_stride, u.as_ptr(), width - 1, v.as_ptr(), width, std::ptr::null(), 0, width, height, 0, &mut buf, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::NullInput, ffiavif_encoder_encode_yuv(enc, y.as_ptr(), y_stride, std::ptr::null(), width, v.as_ptr(), width, std::ptr::null(), 0, width, height, 0, &mut buf, std::ptr::null_mut()));
+        ffiavif_encoder_free(enc);
+    }
+}
+
+#[test]
+fn encode_pixel_formats() {
+    let (width, height) = (6, 4);
+    let rgba: Vec<[u8; 4]> = (0..width * height).map(|i| [(i * 10) as u8, (i * 5) as u8, 255 - i as u8, 100 + i as u8]).collect();
+    let encoded = |enc, pixels: &[u8], stride: isize, format: FfiAvifPixelFormat| unsafe {
+        let mut buf = std::ptr::null_mut();
+        let start = if stride < 0 { pixels.as_ptr().add(pixels.len() - (-stride) as usize) } else { pixels.as_ptr() };
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode_pixels(enc, start, width, height, stride, format as _, &mut buf, std::ptr::null_mut()));
+        let data = std::slice::from_raw_parts((*buf).data, (*buf).len).to_vec();
+        ffiavif_buffer_free(buf);
+        data
+    };
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_speed(enc, 10, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_threads(enc, 1, std::ptr::null_mut()));
+
+        let expected = encoded(enc, &rgba.concat(), width as isize * 4, FfiAvifPixelFormat::Rgba);
+
+        // Bottom-up BGRA with padding after each row
+        let bgra_bottom_up: Vec<u8> = rgba.chunks(width).rev()
+            .flat_map(|row| row.iter().flat_map(|&[r, g, b, a]| [b, g, r, a]).chain([0; 8]))
+            .collect();
+        assert_eq!(expected, encoded(enc, &bgra_bottom_up, -(width as isize * 4 + 8), FfiAvifPixelFormat::Bgra));
+
+        let argb: Vec<u8> = rgba.iter().flat_map(|&[r, g, b, a]| [a, r, g, b]).collect();
+        assert_eq!(expected, encoded(enc, &argb, width as isize * 4, FfiAvifPixelFormat::Argb));
+
+        let mut buf = std::ptr::null_mut();
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_encoder_encode_pixels(enc, argb.as_ptr(), width, height, width as isize * 4, 6, &mut buf, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_encoder_encode_pixels(enc, argb.as_ptr(), width, height, -3, FfiAvifPixelFormat::Rgb as _, &mut buf, std::ptr::null_mut()));
+        ffiavif_encoder_free(enc);
+    }
+}
+
+#[test]
+fn error_out_parameter() {
+    let img = include_bytes!("testimage.png");
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        let mut buf = std::ptr::null_mut();
+        let mut err = std::ptr::null_mut();
+        assert_eq!(FfiAvifErrorCode::DecoderFailure, ffiavif_encoder_encode(enc, img.as_ptr() as *const _, 900, &mut buf, &mut err));
+        assert!(!err.is_null());
+
+        // Unlike last_error_message(), it can be read on another thread
+        let err_addr = err as usize;
+        std::thread::spawn(move || {
+            let err = &*(err_addr as *const FfiAvifErrorInfo);
+            assert_eq!(FfiAvifErrorCode::DecoderFailure, err.code);
+            let message = std::ffi::CStr::from_ptr(err.message).to_str().unwrap();
+            assert!(message.contains("PNG"), "{}", message);
+            assert_eq!(1, err.cause_count);
+            assert!(!std::ffi::CStr::from_ptr(*err.causes).to_bytes().is_empty());
+            ffiavif_error_free(err_addr as *mut FfiAvifErrorInfo);
+        }).join().unwrap();
+
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_speed(enc, 10, &mut err));
+        assert!(err.is_null());
+        ffiavif_encoder_free(enc);
+    }
+}
+
+#[test]
+fn json_config() {
+    let img = include_bytes!("testimage.png");
+    unsafe {
+        let mut buf = std::ptr::null_mut();
+        let json = b"{\"quality\": 70, \"speed\": 10, \"limits\": {\"max_width\": 1000}, \"speed_tweaks\": {\"speed_preset\": 10, \"min_tile_size\": 128, \"cdef\": false}}\0";
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encode_with_json_config(img.as_ptr() as *const _, img.len(), json.as_ptr() as *const _, &mut buf, std::ptr::null_mut()));
+        assert!((*buf).len > 0);
+        ffiavif_buffer_free(buf);
+
+        let mut err = std::ptr::null_mut();
+        let json = b"{\"quality\": 70, \"colour_space\": \"rgb\"}\0";
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_encode_with_json_config(img.as_ptr() as *const _, img.len(), json.as_ptr() as *const _, &mut buf, &mut err));
+        let message = std::ffi::CStr::from_ptr((*err).message).to_str().unwrap();
+        assert!(message.contains("colour_space"), "{}", message);
+        ffiavif_error_free(err);
+
+        let json = b"{\"limits\": {\"max_width\": 100}}\0";
+        assert_eq!(FfiAvifErrorCode::LimitExceeded, ffiavif_encode_with_json_config(img.as_ptr() as *const _, img.len(), json.as_ptr() as *const _, &mut buf, std::ptr::null_mut()));
+        let json = b"{\"quality\": 0}\0";
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_encode_with_json_config(img.as_ptr() as *const _, img.len(), json.as_ptr() as *const _, &mut buf, std::ptr::null_mut()));
+
+        // Speed tweaks can be given partially, but not with values that rav1e can't use
+        let json = b"{\"speed\": 10, \"speed_tweaks\": {\"partition_range\": [16, 64]}}\0";
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encode_with_json_config(img.as_ptr() as *const _, img.len(), json.as_ptr() as *const _, &mut buf, std::ptr::null_mut()));
+        ffiavif_buffer_free(buf);
+
+        // Tweaks that aren't given are the ones for the configured speed
+        let encoded = |json: &[u8]| {
+            let mut buf = std::ptr::null_mut();
+            assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encode_with_json_config(img.as_ptr() as *const _, img.len(), json.as_ptr() as *const _, &mut buf, std::ptr::null_mut()));
+            let data = std::slice::from_raw_parts((*buf).data, (*buf).len).to_vec();
+            ffiavif_buffer_free(buf);
+            data
+        };
+        assert_eq!(encoded(b"{\"speed\": 10, \"threads\": 1, \"speed_tweaks\": {\"cdef\": false}}\0"),
+            encoded(b"{\"speed\": 10, \"threads\": 1, \"speed_tweaks\": {\"speed_preset\": 10, \"cdef\": false}}\0"));
+        assert_ne!(encoded(b"{\"speed\": 10, \"threads\": 1, \"speed_tweaks\": {\"cdef\": false}}\0"),
+            encoded(b"{\"speed\": 10, \"threads\": 1, \"speed_tweaks\": {\"speed_preset\": 4, \"cdef\": false}}\0"));
+        for json in [&b"{\"speed_tweaks\": {\"min_tile_size\": 0}}\0"[..], b"{\"speed_tweaks\": {\"partition_range\": [64, 16]}}\0", b"{\"speed_tweaks\": {\"partition_range\": [5, 16]}}\0"] {
+            assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_encode_with_json_config(img.as_ptr() as *const _, img.len(), json.as_ptr() as *const _, &mut buf, std::ptr::null_mut()));
+        }
+    }
+}
+
+#[test]
+fn version_and_capabilities() {
+    let version = unsafe { std::ffi::CStr::from_ptr(ffiavif_version()) };
+    assert_eq!(env!("CARGO_PKG_VERSION"), version.to_str().unwrap());
+    assert_eq!(FFIAVIF_ABI_VERSION, ffiavif_abi_version());
+
+    let caps = ffiavif_capabilities();
+    assert_ne!(0, caps & FFIAVIF_CAP_DECODE_PNG);
+    assert_ne!(0, caps & FFIAVIF_CAP_DECODE_JPEG);
+    assert_ne!(0, caps & FFIAVIF_CAP_PROBE);
+    assert_eq!(cfg!(feature = "asm"), caps & FFIAVIF_CAP_ASM != 0);
+}
+
+#[test]
+fn high_bit_depth() {
+    // Bit depths from the `pixi` boxes of the color and alpha items
+    let pixi_depths = |data: &[u8]| -> Vec<u8> {
+        data.windows(4).enumerate().filter(|(_, w)| w == b"pixi").map(|(i, _)| data[i + 9]).collect()
+    };
+    let (width, height) = (8, 5);
+    let y: Vec<u16> = (0..width * height).map(|i| (i * 25) as u16).collect();
+    let uv = vec![512u16; width * height];
+    let alpha = vec![1023u16; width * height];
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_speed(enc, 10, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_encoder_set_bit_depth(enc, 9, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_bit_depth(enc, 10, std::ptr::null_mut()));
+
+        let mut buf = std::ptr::null_mut();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode_yuv16(enc, y.as_ptr(), width * 2, uv.as_ptr(), width * 2, uv.as_ptr(), width * 2, alpha.as_ptr(), width * 2, width, height, 1, &mut buf, std::ptr::null_mut()));
+        let data = std::slice::from_raw_parts((*buf).data, (*buf).len);
+        assert_eq!(vec![10, 10], pixi_depths(data));
+        ffiavif_buffer_free(buf);
+
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_encoder_encode_yuv16(enc, y.as_ptr(), width * 2 + 1, uv.as_ptr(), width * 2, uv.as_ptr(), width * 2, std::ptr::null(), 0, width, height, 1, &mut buf, std::ptr::null_mut()));
+
+        // 8-bit pixels are converted to the higher depth
+        let img = include_bytes!("testimage.png");
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_bit_depth(enc, 12, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode(enc, img.as_ptr() as *const _, img.len(), &mut buf, std::ptr::null_mut()));
+        let data = std::slice::from_raw_parts((*buf).data, (*buf).len);
+        let depths = pixi_depths(data);
+        assert!(!depths.is_empty() && depths.iter().all(|&d| d == 12), "{:?}", depths);
+        ffiavif_buffer_free(buf);
+        ffiavif_encoder_free(enc);
+
+        let json = b"{\"bit_depth\": 16}\0";
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_encode_with_json_config(img.as_ptr() as *const _, img.len(), json.as_ptr() as *const _, &mut buf, std::ptr::null_mut()));
+    }
+}
+
+#[test]
+fn sixteen_bit_png() {
+    let (width, height) = (32, 8);
+    // Gradient whose steps are finer than 8 bits can represent
+    let pixels16: Vec<[u16; 4]> = (0..width * height).map(|i| {
+        let v = 0x4000 + (i % width) as u16 * 0x30;
+        [v, v / 2, 0xFFFF - v, if i < width { 0x8000 } else { 0xFFFF }]
+    }).collect();
+    let png16 = lodepng::encode_memory(&pixels16.iter().flatten().flat_map(|c| c.to_be_bytes()).collect::<Vec<u8>>(), width, height, lodepng::ColorType::RGBA, 16).unwrap();
+    let png8 = lodepng::encode_memory(&pixels16.iter().flatten().map(|c| (c >> 8) as u8).collect::<Vec<u8>>(), width, height, lodepng::ColorType::RGBA, 8).unwrap();
+
+    let encoded = |enc, png: &[u8]| unsafe {
+        let mut buf = std::ptr::null_mut();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode(enc, png.as_ptr() as *const _, png.len(), &mut buf, std::ptr::null_mut()));
+        let data = std::slice::from_raw_parts((*buf).data, (*buf).len).to_vec();
+        ffiavif_buffer_free(buf);
+        data
+    };
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_speed(enc, 10, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_threads(enc, 1, std::ptr::null_mut()));
+
+        // 8-bit encoding has no use for the extra precision
+        assert_eq!(encoded(enc, &png8), encoded(enc, &png16));
+
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_bit_depth(enc, 12, std::ptr::null_mut()));
+        assert_ne!(encoded(enc, &png8), encoded(enc, &png16));
+
+        // Decoding 16-bit samples needs more memory than 8-bit ones
+        let max_memory = ravif::Limits::encoder_memory(width, height, 12, false) + width * height * 8;
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_limits(enc, 0, 0, 0, max_memory, std::ptr::null_mut()));
+        encoded(enc, &png8);
+        let mut buf = std::ptr::null_mut();
+        assert_eq!(FfiAvifErrorCode::LimitExceeded, ffiavif_encoder_encode(enc, png16.as_ptr() as *const _, png16.len(), &mut buf, std::ptr::null_mut()));
+        ffiavif_encoder_free(enc);
+    }
+}
+
+#[test]
+fn chroma_subsampling() {
+    // (seq_profile, subsampling_x, subsampling_y) from the first `av1C` box, which is color's
+    let av1c = |data: &[u8]| {
+        let pos = data.windows(4).position(|w| w == b"av1C").unwrap() + 4;
+        (data[pos + 1] >> 5, data[pos + 2] >> 3 & 1, data[pos + 2] >> 2 & 1)
+    };
+    let img = include_bytes!("testimage.png");
+    let (width, height) = (9, 7);
+    let y: Vec<u8> = (0..width * height).map(|i| (i * 3) as u8).collect();
+    let u: Vec<u8> = (0..5 * 4).map(|i| (100 + i) as u8).collect();
+    let v = vec![128u8; 5 * 4];
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_speed(enc, 10, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_encoder_set_chroma_subsampling(enc, 411, std::ptr::null_mut()));
+
+        let mut buf = std::ptr::null_mut();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode(enc, img.as_ptr() as *const _, img.len(), &mut buf, std::ptr::null_mut()));
+        assert_eq!((1, 0, 0), av1c(std::slice::from_raw_parts((*buf).data, (*buf).len)));
+        ffiavif_buffer_free(buf);
+
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_chroma_subsampling(enc, 420, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode(enc, img.as_ptr() as *const _, img.len(), &mut buf, std::ptr::null_mut()));
+        assert_eq!((0, 1, 1), av1c(std::slice::from_raw_parts((*buf).data, (*buf).len)));
+        ffiavif_buffer_free(buf);
+
+        // Odd sizes round the chroma planes up
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode_yuv(enc, y.as_ptr(), width, u.as_ptr(), 5, v.as_ptr(), 5, std::ptr::null(), 0, width, height, 1, &mut buf, std::ptr::null_mut()));
+        ffiavif_buffer_free(buf);
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_encoder_encode_yuv(enc, y.as_ptr(), width, u.as_ptr(), 4, v.as_ptr(), 5, std::ptr::null(), 0, width, height, 1, &mut buf, std::ptr::null_mut()));
+
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_chroma_subsampling(enc, 422, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_bit_depth(enc, 10, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode(enc, img.as_ptr() as *const _, img.len(), &mut buf, std::ptr::null_mut()));
+        assert_eq!((2, 1, 0), av1c(std::slice::from_raw_parts((*buf).data, (*buf).len)));
+        ffiavif_buffer_free(buf);
+
+        // RGB can't be subsampled
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_color_space(enc, 1, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_encoder_encode(enc, img.as_ptr() as *const _, img.len(), &mut buf, std::ptr::null_mut()));
+        ffiavif_encoder_free(enc);
+
+        let json = b"{\"speed\": 10, \"chroma_subsampling\": \"420\"}\0";
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encode_with_json_config(img.as_ptr() as *const _, img.len(), json.as_ptr() as *const _, &mut buf, std::ptr::null_mut()));
+        assert_eq!((0, 1, 1), av1c(std::slice::from_raw_parts((*buf).data, (*buf).len)));
+        ffiavif_buffer_free(buf);
+    }
+}
+
+#[test]
+fn sharp_yuv() {
+    // Red text on white
+    let (width, height) = (16, 16);
+    let pixels: Vec<u8> = (0..width * height)
+        .flat_map(|i| if i % 3 == 0 || (i / width) % 5 == 2 { [255, 0, 0, 255] } else { [255, 255, 255, 255] })
+        .collect();
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_speed(enc, 10, std::ptr::null_mut()));
+        let encode = |enc| {
+            let mut buf = std::ptr::null_mut();
+            assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode_rgba(enc, pixels.as_ptr(), width, height, width * 4, &mut buf, std::ptr::null_mut()));
+            let avif = std::slice::from_raw_parts((*buf).data, (*buf).len).to_vec();
+            ffiavif_buffer_free(buf);
+            avif
+        };
+        let full_color = encode(enc);
+
+        // Without subsampling there's nothing to sharpen
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_sharp_yuv(enc, true, std::ptr::null_mut()));
+        assert_eq!(full_color, encode(enc));
+
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_chroma_subsampling(enc, 420, std::ptr::null_mut()));
+        let sharp = encode(enc);
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_sharp_yuv(enc, false, std::ptr::null_mut()));
+        assert_ne!(sharp, encode(enc));
+        ffiavif_encoder_free(enc);
+
+        let img = include_bytes!("testimage.png");
+        let json = b"{\"speed\": 10, \"chroma_subsampling\": \"420\", \"sharp_yuv\": true}\0";
+        let mut buf = std::ptr::null_mut();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encode_with_json_config(img.as_ptr() as *const _, img.len(), json.as_ptr() as *const _, &mut buf, std::ptr::null_mut()));
+        ffiavif_buffer_free(buf);
+    }
+}
+
+#[test]
+fn color_description() {
+    // (primaries, transfer, matrix, full range) from the `colr` box
+    let colr = |data: &[u8]| {
+        let pos = data.windows(8).position(|w| w == b"colrnclx").unwrap() + 8;
+        let code = |i: usize| u16::from_be_bytes([data[pos + i], data[pos + i + 1]]);
+        (code(0), code(2), code(4), data[pos + 6] >> 7)
+    };
+    let img = include_bytes!("testimage.png");
+    let y = vec![100u8; 16 * 16];
+    let uv = vec![128u8; 16 * 16];
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_speed(enc, 10, std::ptr::null_mut()));
+        let encode = |enc| {
+            let mut buf = std::ptr::null_mut();
+            assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode(enc, img.as_ptr() as *const _, img.len(), &mut buf, std::ptr::null_mut()));
+            let desc = colr(std::slice::from_raw_parts((*buf).data, (*buf).len));
+            ffiavif_buffer_free(buf);
+            desc
+        };
+        assert_eq!((1, 13, 1, 1), encode(enc));
+
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_encoder_set_matrix_coefficients(enc, 0, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_encoder_set_color_primaries(enc, 2, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_encoder_set_transfer_characteristics(enc, 4, std::ptr::null_mut()));
+
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_matrix_coefficients(enc, 6, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_color_primaries(enc, 12, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_transfer_characteristics(enc, 16, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_bit_depth(enc, 10, std::ptr::null_mut()));
+        assert_eq!((12, 16, 6, 1), encode(enc));
+
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_matrix_coefficients(enc, 8, std::ptr::null_mut()));
+        assert_eq!((12, 16, 8, 1), encode(enc));
+
+        // RGB is stored with the identity matrix
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_color_space(enc, 1, std::ptr::null_mut()));
+        assert_eq!((12, 16, 0, 1), encode(enc));
+
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_color_space(enc, 0, std::ptr::null_mut()));
+        let mut buf = std::ptr::null_mut();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode_yuv(enc, y.as_ptr(), 16, uv.as_ptr(), 16, uv.as_ptr(), 16, std::ptr::null(), 0, 16, 16, 0, &mut buf, std::ptr::null_mut()));
+        assert_eq!((12, 16, 8, 0), colr(std::slice::from_raw_parts((*buf).data, (*buf).len)));
+        ffiavif_buffer_free(buf);
+        ffiavif_encoder_free(enc);
+
+        let json = b"{\"speed\": 10, \"matrix_coefficients\": \"bt2020ncl\", \"color_primaries\": \"bt2020\", \"transfer_characteristics\": \"hlg\"}\0";
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encode_with_json_config(img.as_ptr() as *const _, img.len(), json.as_ptr() as *const _, &mut buf, std::ptr::null_mut()));
+        assert_eq!((9, 18, 9, 1), colr(std::slice::from_raw_parts((*buf).data, (*buf).len)));
+        ffiavif_buffer_free(buf);
+    }
+}
+
+#[test]
+fn odd_width_last_row() {
+    // Rows of planes narrower than the encoder's padded frame must not shift or get cut off
+    let (width, height) = (9, 7);
+    let pixels: Vec<u8> = (0..width * height).flat_map(|i| [(i * 4) as u8, 100, 200, 128]).collect();
+    let mut changed = pixels.clone();
+    for px in changed[(height - 1) * width * 4..].chunks_exact_mut(4) {
+        px.copy_from_slice(&[255, 0, 0, 255]);
+    }
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_speed(enc, 10, std::ptr::null_mut()));
+        let encode = |enc, pixels: &[u8]| {
+            let mut buf = std::ptr::null_mut();
+            assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_encode_rgba(enc, pixels.as_ptr(), width, height, width * 4, &mut buf, std::ptr::null_mut()));
+            let avif = std::slice::from_raw_parts((*buf).data, (*buf).len).to_vec();
+            ffiavif_buffer_free(buf);
+            avif
+        };
+        for subsampling in [444, 420] {
+            assert_eq!(FfiAvifErrorCode::Ok, ffiavif_encoder_set_chroma_subsampling(enc, subsampling, std::ptr::null_mut()));
+            assert_ne!(encode(enc, &pixels), encode(enc, &changed), "{}", subsampling);
+        }
+        ffiavif_encoder_free(enc);
+    }
+}
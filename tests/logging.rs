@@ -0,0 +1,54 @@
+// The log callback is global, so it's tested in its own process, where no other tests log errors
+
+use ffiavif::*;
+use std::os::raw::{c_char, c_void};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+static MESSAGES: Mutex<Vec<(FfiAvifLogLevel, String)>> = Mutex::new(Vec::new());
+static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn record_log(level: FfiAvifLogLevel, _target: *const c_char, message: *const c_char, user_data: *mut c_void) {
+    let messages = unsafe { &*(user_data as *const Mutex<Vec<(FfiAvifLogLevel, String)>>) };
+    let message = unsafe { std::ffi::CStr::from_ptr(message) }.to_string_lossy().into_owned();
+    messages.lock().unwrap().push((level, message));
+}
+
+extern "C" fn unregister_log(_: FfiAvifLogLevel, _: *const c_char, _: *const c_char, user_data: *mut c_void) {
+    unsafe { &*(user_data as *const AtomicUsize) }.fetch_add(1, Ordering::SeqCst);
+    unsafe { ffiavif_set_log_callback(None, std::ptr::null_mut(), std::ptr::null_mut()); }
+}
+
+fn encode_invalid_file() {
+    unsafe {
+        let enc = ffiavif_encoder_new();
+        let mut buf = std::ptr::null_mut();
+        assert_eq!(FfiAvifErrorCode::UnsupportedFormat, ffiavif_encoder_encode(enc, b"GIF89a".as_ptr() as *const _, 6, &mut buf, std::ptr::null_mut()));
+        ffiavif_encoder_free(enc);
+    }
+}
+
+#[test]
+fn log_callback() {
+    unsafe {
+        // Logging turned off by the host stays off
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_set_log_max_level(FfiAvifLogLevel::Off as _, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_set_log_callback(Some(record_log), &MESSAGES as *const _ as *mut _, std::ptr::null_mut()));
+        encode_invalid_file();
+        assert!(MESSAGES.lock().unwrap().is_empty());
+
+        assert_eq!(FfiAvifErrorCode::InvalidArgument, ffiavif_set_log_max_level(6, std::ptr::null_mut()));
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_set_log_max_level(FfiAvifLogLevel::Warn as _, std::ptr::null_mut()));
+        encode_invalid_file();
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_set_log_callback(None, std::ptr::null_mut(), std::ptr::null_mut()));
+
+        // The callback may replace itself
+        assert_eq!(FfiAvifErrorCode::Ok, ffiavif_set_log_callback(Some(unregister_log), &CALLS as *const _ as *mut _, std::ptr::null_mut()));
+        encode_invalid_file();
+        encode_invalid_file();
+        assert_eq!(1, CALLS.load(Ordering::SeqCst));
+    }
+    let messages = MESSAGES.lock().unwrap();
+    assert!(messages.iter().any(|(level, msg)| *level == FfiAvifLogLevel::Error && msg.contains("LAST_ERROR")));
+    assert!(messages.iter().all(|(level, _)| *level as i32 <= FfiAvifLogLevel::Warn as i32));
+}
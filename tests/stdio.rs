@@ -42,3 +42,48 @@ fn path_to_stdout() -> Result<(), std::io::Error> {
     avif_parse::read_avif(&mut data.as_slice()).unwrap();
     Ok(())
 }
+
+#[test]
+fn json_config_file() -> Result<(), std::io::Error> {
+    let dir = std::env::temp_dir().join(format!("cavif-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let good = dir.join("good.json");
+    let bad = dir.join("bad.json");
+    std::fs::write(&good, r#"{"quality": 60, "speed": 10, "color_space": "rgb"}"#)?;
+    std::fs::write(&bad, r#"{"quality": 60, "sped": 10}"#)?;
+
+    let run = |config: &std::path::Path| std::process::Command::new(env!("CARGO_BIN_EXE_cavif"))
+        .stdin(Stdio::null())
+        .arg("tests/testimage.png")
+        .arg("--config").arg(config)
+        .arg("-o").arg("-")
+        .output();
+
+    let out = run(&good)?;
+    assert!(out.status.success());
+    assert_eq!(&out.stdout[4..4+8], b"ftypavif");
+
+    let out = run(&bad)?;
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("sped"));
+
+    let out_of_range = dir.join("out_of_range.json");
+    std::fs::write(&out_of_range, r#"{"speed": 10, "speed_tweaks": {"min_tile_size": 0}}"#)?;
+    let out = run(&out_of_range)?;
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("tile size"));
+
+    // Limits are checked before decoding. The file claims to be 20000x20000, but has almost no pixel data.
+    let limited = dir.join("limited.json");
+    std::fs::write(&limited, r#"{"limits": {"max_pixels": 1000}}"#)?;
+    let out = std::process::Command::new(env!("CARGO_BIN_EXE_cavif"))
+        .stdin(Stdio::null())
+        .arg("tests/huge_header.png")
+        .arg("--config").arg(&limited)
+        .arg("-o").arg("-")
+        .output()?;
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("over the limit"));
+
+    std::fs::remove_dir_all(&dir)
+}